@@ -0,0 +1,227 @@
+use bevy::input::mouse::{MouseMotion, MouseScrollUnit, MouseWheel};
+use bevy::prelude::*;
+
+/// How far (in radians) the camera turns per pixel of mouse movement
+const ORBIT_SENSITIVITY: f32 = 0.005;
+
+/// How close (in radians) the camera may get to looking straight up or down
+const PITCH_MARGIN: f32 = 0.05;
+
+/// How fast (in units per second) the camera moves in fly mode
+const FLY_SPEED: f32 = 10.0;
+
+/// Speed multiplier while holding shift in fly mode
+const FLY_BOOST: f32 = 3.0;
+
+/// Closest the orbit camera may zoom in to its focus
+const MIN_ZOOM_DISTANCE: f32 = 3.0;
+
+/// Furthest the orbit camera may zoom out from its focus
+const MAX_ZOOM_DISTANCE: f32 = 80.0;
+
+/// Fraction of the distance each scroll wheel notch zooms by
+const ZOOM_STEP: f32 = 0.1;
+
+/// Scroll distance (in pixels) that counts as one notch on touchpads and the like
+const PIXELS_PER_NOTCH: f32 = 50.0;
+
+/// How quickly the camera catches up with the zoom target (higher is snappier)
+const ZOOM_SMOOTHING: f32 = 10.0;
+
+/// Whether the camera orbits its focus or flies freely
+#[derive(Resource, Default, Debug, PartialEq, Eq)]
+pub enum CameraMode {
+    /// Right mouse drag orbits the camera around its focus
+    #[default]
+    Orbit,
+    /// WASD moves, Q/E lower/raise and right mouse drag looks around
+    Fly,
+}
+
+/// The distance the orbit camera is easing towards after scrolling
+#[derive(Resource, Default)]
+pub struct CameraZoom {
+    /// None until the first scroll, and again whenever the camera leaves orbit mode
+    target_distance: Option<f32>,
+}
+
+/// Keeps the camera on a sphere around `focus`, looking at it.  The angles are in radians.
+#[derive(Component)]
+pub struct OrbitCamera {
+    pub focus: Vec3,
+    pub distance: f32,
+    /// Angle around the Y axis, measured from +Z towards +X
+    pub yaw: f32,
+    /// Angle above the XZ plane
+    pub pitch: f32,
+}
+
+impl OrbitCamera {
+    /// Creates an orbit camera that starts out at `position`, looking at `focus`
+    pub fn looking_at(position: Vec3, focus: Vec3) -> Self {
+        let offset = position - focus;
+        let distance = offset.length();
+        OrbitCamera {
+            focus,
+            distance,
+            yaw: offset.x.atan2(offset.z),
+            pitch: (offset.y / distance).asin(),
+        }
+    }
+
+    /// The camera transform for the current angles and distance
+    pub fn transform(&self) -> Transform {
+        let offset = Vec3::new(
+            self.pitch.cos() * self.yaw.sin(),
+            self.pitch.sin(),
+            self.pitch.cos() * self.yaw.cos(),
+        ) * self.distance;
+        Transform::from_translation(self.focus + offset).looking_at(self.focus, Vec3::Y)
+    }
+}
+
+/// Orbit the camera around its focus while the right mouse button is held
+pub fn orbit_camera(
+    mode: Res<CameraMode>,
+    mouse: Res<Input<MouseButton>>,
+    mut motion: EventReader<MouseMotion>,
+    mut query: Query<(&mut OrbitCamera, &mut Transform)>,
+) {
+    // add up all of this frame's mouse movement
+    let delta: Vec2 = motion.read().map(|event| event.delta).sum();
+    if *mode != CameraMode::Orbit || !mouse.pressed(MouseButton::Right) || delta == Vec2::ZERO {
+        return;
+    }
+
+    for (mut orbit, mut transform) in &mut query {
+        orbit.yaw -= delta.x * ORBIT_SENSITIVITY;
+        // clamp the pitch so the camera can't flip over the poles
+        let max_pitch = std::f32::consts::FRAC_PI_2 - PITCH_MARGIN;
+        orbit.pitch = (orbit.pitch + delta.y * ORBIT_SENSITIVITY).clamp(-max_pitch, max_pitch);
+        *transform = orbit.transform();
+    }
+}
+
+/// Zoom the orbit camera towards or away from its focus with the mouse wheel, easing into the
+/// new distance rather than jumping to it
+pub fn zoom_camera(
+    mode: Res<CameraMode>,
+    time: Res<Time<Real>>,
+    mut wheel: EventReader<MouseWheel>,
+    mut zoom: ResMut<CameraZoom>,
+    mut query: Query<(&mut OrbitCamera, &mut Transform)>,
+) {
+    // add up all of this frame's scrolling, in notches
+    let notches: f32 = wheel
+        .read()
+        .map(|event| match event.unit {
+            MouseScrollUnit::Line => event.y,
+            MouseScrollUnit::Pixel => event.y / PIXELS_PER_NOTCH,
+        })
+        .sum();
+    if *mode != CameraMode::Orbit {
+        // the camera will have moved by the time it's back in orbit mode
+        zoom.target_distance = None;
+        return;
+    }
+
+    for (mut orbit, mut transform) in &mut query {
+        let target = zoom.target_distance.get_or_insert(orbit.distance);
+        if notches != 0.0 {
+            // zoom by a fraction of the distance so it feels the same near and far
+            *target = (*target * (1.0 - ZOOM_STEP).powf(notches))
+                .clamp(MIN_ZOOM_DISTANCE, MAX_ZOOM_DISTANCE);
+        }
+        if orbit.distance == *target {
+            continue;
+        }
+
+        // ease towards the target independently of the frame rate, and snap once close enough
+        let t = 1.0 - (-ZOOM_SMOOTHING * time.delta_seconds()).exp();
+        orbit.distance += (*target - orbit.distance) * t;
+        if (orbit.distance - *target).abs() < 0.001 {
+            orbit.distance = *target;
+        }
+        *transform = orbit.transform();
+    }
+}
+
+/// Fly the camera around freely: WASD moves, Q/E lower/raise, shift speeds up and right mouse
+/// drag looks around
+pub fn fly_camera(
+    mode: Res<CameraMode>,
+    time: Res<Time<Real>>,
+    kbd: Res<Input<KeyCode>>,
+    mouse: Res<Input<MouseButton>>,
+    mut motion: EventReader<MouseMotion>,
+    mut query: Query<&mut Transform, With<OrbitCamera>>,
+) {
+    let delta: Vec2 = motion.read().map(|event| event.delta).sum();
+    if *mode != CameraMode::Fly {
+        return;
+    }
+
+    for mut transform in &mut query {
+        // look around
+        if mouse.pressed(MouseButton::Right) && delta != Vec2::ZERO {
+            let (yaw, pitch, _) = transform.rotation.to_euler(EulerRot::YXZ);
+            let max_pitch = std::f32::consts::FRAC_PI_2 - PITCH_MARGIN;
+            let yaw = yaw - delta.x * ORBIT_SENSITIVITY;
+            let pitch = (pitch - delta.y * ORBIT_SENSITIVITY).clamp(-max_pitch, max_pitch);
+            transform.rotation = Quat::from_euler(EulerRot::YXZ, yaw, pitch, 0.0);
+        }
+
+        // move relative to where the camera is facing
+        let forward = transform.forward();
+        let right = transform.right();
+        let mut direction = Vec3::ZERO;
+        if kbd.pressed(KeyCode::W) {
+            direction += forward;
+        }
+        if kbd.pressed(KeyCode::S) {
+            direction -= forward;
+        }
+        if kbd.pressed(KeyCode::D) {
+            direction += right;
+        }
+        if kbd.pressed(KeyCode::A) {
+            direction -= right;
+        }
+        if kbd.pressed(KeyCode::E) {
+            direction += Vec3::Y;
+        }
+        if kbd.pressed(KeyCode::Q) {
+            direction -= Vec3::Y;
+        }
+
+        let mut speed = FLY_SPEED;
+        if kbd.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]) {
+            speed *= FLY_BOOST;
+        }
+        transform.translation += direction.normalize_or_zero() * speed * time.delta_seconds();
+    }
+}
+
+/// Switch between orbit and fly mode when pressing F.  Going back to orbit mode points the
+/// camera at its focus again from wherever it flew to.
+pub fn toggle_camera_mode(
+    mut mode: ResMut<CameraMode>,
+    kbd: Res<Input<KeyCode>>,
+    mut query: Query<(&mut OrbitCamera, &mut Transform)>,
+) {
+    if !kbd.just_pressed(KeyCode::F) {
+        return;
+    }
+
+    *mode = match *mode {
+        CameraMode::Orbit => CameraMode::Fly,
+        CameraMode::Fly => {
+            for (mut orbit, mut transform) in &mut query {
+                *orbit = OrbitCamera::looking_at(transform.translation, orbit.focus);
+                *transform = orbit.transform();
+            }
+            CameraMode::Orbit
+        }
+    };
+    info!("Camera mode: {:?}", *mode);
+}
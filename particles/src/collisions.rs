@@ -0,0 +1,102 @@
+use bevy::prelude::*;
+
+/// Running tally of collisions, filled in by whoever listens for the collision events
+#[derive(Resource, Default)]
+pub struct CollisionStats {
+    /// Number of times a particle has started touching the ground
+    pub ground_hits: u64,
+    /// Number of particles retired for falling below the kill plane (rather than expiring)
+    pub kill_plane_removals: u64,
+}
+
+/// Marker to find the text entity so we can update it
+#[derive(Component)]
+pub struct CollisionCounterText;
+
+pub fn setup_collision_counter(mut commands: Commands) {
+    // create our UI root node
+    // this is the wrapper/container for the text
+    let root = commands
+        .spawn(NodeBundle {
+            // give it a dark background for readability
+            background_color: BackgroundColor(Color::BLACK.with_a(0.5)),
+            // make it "always on top" by setting the Z index to maximum
+            // we want it to be displayed over all other UI
+            z_index: ZIndex::Global(i32::MAX),
+            style: Style {
+                position_type: PositionType::Absolute,
+                // position it at the bottom-right corner
+                // 1% away from the bottom window edge
+                right: Val::Percent(1.),
+                bottom: Val::Percent(1.),
+                // set top/left to Auto, so it can be
+                // automatically sized depending on the text
+                top: Val::Auto,
+                left: Val::Auto,
+                // give it some padding for readability
+                padding: UiRect::all(Val::Px(4.0)),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .id();
+    // create our text
+    let text_collisions = commands
+        .spawn((
+            CollisionCounterText,
+            TextBundle {
+                // use separate sections, so it is easy to update just the numbers
+                text: Text::from_sections([
+                    TextSection {
+                        value: "Ground hits: ".into(),
+                        style: TextStyle {
+                            font_size: 16.0,
+                            color: Color::WHITE,
+                            ..default()
+                        },
+                    },
+                    TextSection {
+                        value: "0".into(),
+                        style: TextStyle {
+                            font_size: 16.0,
+                            color: Color::WHITE,
+                            ..default()
+                        },
+                    },
+                    TextSection {
+                        value: "  Fell out: ".into(),
+                        style: TextStyle {
+                            font_size: 16.0,
+                            color: Color::WHITE,
+                            ..default()
+                        },
+                    },
+                    TextSection {
+                        value: "0".into(),
+                        style: TextStyle {
+                            font_size: 16.0,
+                            color: Color::WHITE,
+                            ..default()
+                        },
+                    },
+                ]),
+                ..Default::default()
+            },
+        ))
+        .id();
+    commands.entity(root).push_children(&[text_collisions]);
+}
+
+pub fn collision_counter_update_system(
+    stats: Res<CollisionStats>,
+    mut query: Query<&mut Text, With<CollisionCounterText>>,
+) {
+    if !stats.is_changed() {
+        return;
+    }
+
+    for mut text in &mut query {
+        text.sections[1].value = stats.ground_hits.to_string();
+        text.sections[3].value = stats.kill_plane_removals.to_string();
+    }
+}
@@ -2,6 +2,8 @@ use bevy::diagnostic::DiagnosticsStore;
 use bevy::diagnostic::FrameTimeDiagnosticsPlugin;
 use bevy::prelude::*;
 
+use crate::Configuration;
+
 /// Marker to find the container entity so we can show/hide the FPS counter
 #[derive(Component)]
 pub struct FpsRoot;
@@ -45,7 +47,7 @@ pub fn setup_fps_counter(mut commands: Commands) {
         .spawn((
             FpsText,
             TextBundle {
-                // use two sections, so it is easy to update just the number
+                // use separate sections, so it is easy to update just the numbers
                 text: Text::from_sections([
                     TextSection {
                         value: "FPS: ".into(),
@@ -69,6 +71,23 @@ pub fn setup_fps_counter(mut commands: Commands) {
                             ..default()
                         },
                     },
+                    // the spawn count goes alongside, so screenshots capture both
+                    TextSection {
+                        value: "  Batch: ".into(),
+                        style: TextStyle {
+                            font_size: 16.0,
+                            color: Color::WHITE,
+                            ..default()
+                        },
+                    },
+                    TextSection {
+                        value: "".into(),
+                        style: TextStyle {
+                            font_size: 16.0,
+                            color: Color::WHITE,
+                            ..default()
+                        },
+                    },
                 ]),
                 ..Default::default()
             },
@@ -79,9 +98,12 @@ pub fn setup_fps_counter(mut commands: Commands) {
 
 pub fn fps_text_update_system(
     diagnostics: Res<DiagnosticsStore>,
+    configuration: Res<Configuration>,
     mut query: Query<&mut Text, With<FpsText>>,
 ) {
     for mut text in &mut query {
+        text.sections[3].value = configuration.spawn_count.to_string();
+
         // try to get a "smoothed" FPS value from Bevy
         if let Some(value) = diagnostics
             .get(FrameTimeDiagnosticsPlugin::FPS)
@@ -1,80 +1,1902 @@
 use bevy::prelude::*;
-use bevy::{core_pipeline::fxaa::Fxaa, pbr::PointLightShadowMap};
+use bevy::window::PrimaryWindow;
+use bevy::{
+    core_pipeline::{bloom::BloomSettings, fxaa::Fxaa},
+    pbr::{FogFalloff, FogSettings, NotShadowCaster, PointLightShadowMap},
+};
 
+use bevy_egui::{egui, EguiContexts, EguiPlugin};
 use bevy_rapier3d::prelude::*;
+use clap::Parser;
 use rand::*;
-use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use bevy::diagnostic::FrameTimeDiagnosticsPlugin;
+use bevy::app::{AppExit, ScheduleRunnerPlugin};
+use bevy::asset::LoadState;
+use bevy::core::FrameCount;
+use bevy::diagnostic::{DiagnosticsPlugin, DiagnosticsStore, FrameTimeDiagnosticsPlugin};
+use bevy::ecs::query::Has;
+use bevy::hierarchy::HierarchyPlugin;
+use bevy::input::InputPlugin;
+use bevy::render::mesh::VertexAttributeValues;
+use bevy::render::texture::{
+    ImageAddressMode, ImageLoaderSettings, ImageSampler, ImageSamplerDescriptor,
+};
+use bevy::render::view::screenshot::ScreenshotManager;
+use bevy::scene::ScenePlugin;
+use bevy::transform::TransformPlugin;
 
 // FPS counter module
 mod fps;
 use fps::{fps_counter_showhide, fps_text_update_system, setup_fps_counter};
 
-// Compile time constants
+// Camera controls module
+mod camera;
+use camera::{
+    fly_camera, orbit_camera, toggle_camera_mode, zoom_camera, CameraMode, CameraZoom, OrbitCamera,
+};
+
+// On-screen status line module
+mod status;
+use status::{setup_status_text, StatusNotice, StatusText};
+
+// Collision counter module
+mod collisions;
+use collisions::{collision_counter_update_system, setup_collision_counter, CollisionStats};
+
+// Particle count module
+mod particle_count;
+use particle_count::{particle_count_update_system, setup_particle_counter};
+
+mod surface;
+use surface::SurfaceSampler;
+
+mod presets;
+use presets::{select_preset, ActivePreset};
+
+mod trails;
+use trails::{draw_trails, record_trails, toggle_trails, TrailPoints};
+
+mod sun;
+use sun::{move_sun, Sun, SUN_ILLUMINANCE};
+
+// Compile time constants (the ones that can be tuned at runtime are only defaults for the
+// matching Configuration fields)
 const PARTICLE_RADIUS: f32 = 0.2;
+const MIN_PARTICLE_RADIUS: f32 = 0.1; // Smallest radius a new particle is given.
+const MAX_PARTICLE_RADIUS: f32 = 0.3; // Largest radius a new particle is given.
 const SPAWN_COUNT: usize = 30; // Number of particles to spawn when it's time to do so.
-const PARTICLE_EXPIRE_TIME_SECS: u64 = 10; // Number of seconds until each particle despawns.
+const PARTICLE_EXPIRE_TIME_SECS: f32 = 10.0; // Number of seconds until each particle despawns.
+const LIFETIME_RANGE: (f32, f32) = (PARTICLE_EXPIRE_TIME_SECS, PARTICLE_EXPIRE_TIME_SECS); // Default range (in seconds) each particle's lifetime is drawn from.
 const PARTICLE_RESPAWN_TIME_MS: u64 = 100; // How often (in milliseconds) to wait until spawning more particles.
-const MAX_SPAWN_OFFSET: f32 = 3.0; // Max offset (in X, Z) of new particle location.
+const MAX_SPAWN_OFFSET: f32 = 3.0; // Max offset (+/- in X, Z) of new particle location.
+const SPAWN_HEIGHT: f32 = 4.5; // Height of new particles above their emitter.
 const INITIAL_VELOCITY: f32 = 2.0; // Initial velocity vector magnitude of new particles.
+const INITIAL_VELOCITY_STD_DEV: f32 = 0.4; // Standard deviation of the initial speed of new particles.
+const SPAWN_RATE: f32 = 300.0; // Particles per second in continuous emission mode.
+const MAX_CATCH_UP_BATCHES: usize = 5; // Max batches spawned at once to catch up after a long frame.
+const MIN_SPAWN_DELTA_MS: u64 = 10; // Shortest batch interval reachable with '='.
+const MAX_SPAWN_DELTA_MS: u64 = 5000; // Longest batch interval reachable with '-'.
+const RAMP_INITIAL_RATE: f32 = 0.0; // Rate (particles per second) a spawn ramp ('R') starts at.
+const RAMP_TARGET_RATE: f32 = 3000.0; // Rate (particles per second) a spawn ramp ends at.
+const RAMP_DURATION_SECS: f32 = 60.0; // How long a spawn ramp takes to reach its target rate.
+const PULSE_AMPLITUDE: f32 = 0.9; // How far (as a fraction of the rate) pulsed emission swings.
+const PULSE_PERIOD_SECS: f32 = 2.0; // Seconds from one wave of pulsed emission to the next.
+const GRAVITY_STEP: f32 = 1.25; // Factor PageUp / PageDown scale gravity by.
+const MIN_GRAVITY: f32 = 0.5; // Weakest (non-zero) gravity reachable with PageDown.
+const MAX_GRAVITY: f32 = 50.0; // Strongest gravity reachable with PageUp.
+const WIND_STEP: f32 = 0.5; // How much the arrow keys change the wind force (in X, Z).
+const ATTRACTOR_STRENGTH: f32 = 20.0; // Strength of the attractor (force at a distance of 1).
+const ATTRACTOR_MIN_DISTANCE: f32 = 0.5; // Distance below which the attractor's pull stops growing.
+const ATTRACTOR_MAX_FORCE: f32 = 20.0; // Cap on the attractor's force, to keep the solver stable.
+const EXPLOSION_STRENGTH: f32 = 5.0; // Strength of an explosion ('X') (impulse at a distance of 1).
+const EXPLOSION_MIN_DISTANCE: f32 = 0.5; // Distance below which an explosion's push stops growing.
+const EXPLOSION_MAX_IMPULSE: f32 = 5.0; // Cap on the impulse an explosion gives a particle.
 const GROUND_RADIUS: f32 = 10.0; // The "radius" of the ground plane.
+const GROUND_HEIGHT: f32 = 0.5; // Height of the ground plane's surface.
+const GROUND_COLOR: Color = Color::rgb(0.4, 0.4, 0.4); // Color of the ground when it isn't textured.
+const GROUND_TEXTURE: &str = "textures/ground_grid.png"; // Default ground texture (in the assets folder).
+const GROUND_UV_TILING: f32 = 5.0; // Default number of times the ground texture repeats along each side.
+const ORBIT_RADIUS: f32 = 5.0; // Radius of the orbiting emitter's circular path.
+const ORBIT_ANGULAR_SPEED: f32 = 0.5; // Angular speed (in radians per second) of the orbiting emitter.
+const MAX_PARTICLES: usize = 5000; // Default cap on the number of live particles.
+const CAP_WARNING_INTERVAL_SECS: f32 = 5.0; // Min time between 'particle cap reached' warnings.
+const BURST_SIZE: usize = 500; // Number of particles spawned by a single burst ('B').
+const POOL_SIZE: usize = 2000; // Max number of expired particles kept around for reuse.
+const SPEED_COLOR_MIN: f32 = 0.0; // Speed (m/s) shown in blue when coloring by speed.
+const SPEED_COLOR_MAX: f32 = 10.0; // Speed (m/s) (and above) shown in red when coloring by speed.
+const HEIGHT_COLOR_MAX: f32 = SPAWN_HEIGHT + 2.0; // Height shown in the 'high' color when coloring by height.
+const FADE_OUT_SECS: f32 = 1.5; // Default for how long before expiring a particle starts to fade out.
+const SHRINK_SECS: f32 = 1.5; // Default for how long before expiring a particle starts to shrink.
+const SHRINK_COLLIDER_MIN_SCALE: f32 = 0.25; // Scale below which a shrinking particle stops colliding.
+const PALETTE_SIZE: usize = 12; // Number of hues to pick from when coloring particles randomly.
+const PARTICLE_GROUP: Group = Group::GROUP_1; // Collision group every particle belongs to.
+const FLASH_SECS: f32 = 0.3; // How long the flash where a particle was retired lasts.
+const FLASH_SCALE_RANGE: (f32, f32) = (1.2, 2.5); // Size of a flash as it starts and ends, relative to a particle.
+const FLASH_FADE_STEPS: usize = 8; // Number of materials a flash steps through as it fades.
+const FLASH_COLOR: Color = Color::rgb(1.0, 0.95, 0.8); // Color of the flash.
+const EMISSIVE_STRENGTH: f32 = 0.0; // How brightly particles glow (0 = not at all).
+const AMBIENT_BRIGHTNESS: f32 = 0.2; // Brightness of the ambient light (by day, with the sun).
+const AMBIENT_BRIGHTNESS_STEP: f32 = 0.05; // How much F5 / F6 lower / raise the ambient brightness by.
+const MAX_AMBIENT_BRIGHTNESS: f32 = 1.0; // Brightest the ambient light can be turned up to.
+const DAY_LENGTH_SECS: f32 = 120.0; // Seconds for the sun to go round once, with sun lighting.
+const MAX_ANGULAR_SPEED: f32 = 4.0; // Max angular speed (in radians per second) of new particles.
+const LEGACY_JITTER: f32 = 0.25; // Sideways spread of the legacy emission shape (per unit along the axis).
+const SPREAD_ANGLE: f32 = 0.5; // Default full apex angle (in radians) of the emission cone.
+const SPREAD_ANGLE_STEP: f32 = std::f32::consts::PI / 36.0; // How much '[' / ']' change the spread angle.
+const RESTITUTION: f32 = 0.0; // Default bounciness of new particles (0 = no bounce, 1 = no energy lost).
+const RESTITUTION_STEP: f32 = 0.1; // How much ',' / '.' change the restitution.
+const FRICTION: f32 = 0.5; // Default friction coefficient of the particles and the ground.
+const FRICTION_STEP: f32 = 0.1; // How much ';' / ''' change the friction.
+const MAX_FRICTION: f32 = 2.0; // Highest friction coefficient reachable with '''.
+const MAX_SPAWN_COUNT: usize = 10_000; // Largest spawn count the keys and control panel allow.
+const SPAWN_COUNT_STEP: f32 = 1.5; // Factor the numpad + and - keys change the spawn count by.
+const PANEL_RADIUS_RANGE: (f32, f32) = (0.05, 1.0); // Radii the control panel offers.
+const PANEL_SPEED_RANGE: (f32, f32) = (0.1, 20.0); // Initial velocities the control panel offers.
+const PANEL_EXPIRE_TIME_RANGE: (f32, f32) = (0.5, 60.0); // Expire times the control panel offers.
+const PANEL_FOG_DISTANCE_RANGE: (f32, f32) = (0.0, 200.0); // Fog distances the control panel offers.
+const FOG_START: f32 = 30.0; // Distance from the camera the fog starts at.
+const FOG_END: f32 = 60.0; // Distance from the camera beyond which everything is lost in the fog.
+const SPIRAL_STEP: f32 = 0.3; // Radians the spiral pattern turns between successive particles.
+const TARGET_FPS: f32 = 55.0; // Frame rate the adaptive spawn rate aims to hold.
+const ADAPTIVE_RATE_RANGE: (f32, f32) = (0.1, 1.0); // Range of the adaptive spawn rate, as a fraction of the normal rate.
+const ADAPTIVE_RATE_GAIN: f32 = 0.5; // How strongly (per second) the adaptive rate reacts to the FPS being off target.
+const CONFIG_FILE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/config.ron"); // File (next to Cargo.toml, wherever the sample is run from) the tunable settings are read from.
+const CURTAIN_EMITTER_COUNT: usize = 8; // Default number of emitters in the curtain along the back edge.
+const CURTAIN_STREAM_RADIUS: f32 = 0.05; // Radius of the disc each curtain stream spawns from.
+const NOZZLE_TURN_SPEED: f32 = 1.0; // Radians per second Ctrl + the arrow keys steer the nozzle by.
+const TORUS_POSITION: Vec3 = Vec3::new(0.0, 8.0, 0.0); // Where the demo torus hangs above the ground.
+const TORUS_SPEED_FACTOR: f32 = 0.25; // Fraction of the usual launch speed particles leave the torus at.
+const TRAIL_LENGTH: usize = 30; // Default number of recent positions kept for each particle's trail.
+const KILL_PLANE_Y: f32 = -20.0; // Particles that fall below this height are retired straight away.
+const RAIN_HEIGHT: f32 = 15.0; // Height rain drops spawn at.
+const RAIN_SPEED: f32 = 5.0; // Downward speed rain drops start with.
+const RAIN_JITTER: f32 = 0.5; // Largest sideways speed rain drops start with.
+const RAIN_EXPIRE_TIME_SECS: f32 = 4.0; // Number of seconds until each rain drop despawns.
+const SPLASH_THRESHOLD_SPEED: f32 = 6.0; // Particles hitting the ground faster than this splash.
+const SPLASH_COUNT: usize = 5; // Number of droplets in each splash.
+const SPLASH_SCALE: f32 = 0.3; // Size of splash droplets, relative to a regular particle.
+const SPLASH_SPEED: f32 = 2.0; // Speed splash droplets fly off at.
+const SPLASH_EXPIRE_TIME_SECS: f32 = 1.0; // Number of seconds until each splash droplet despawns.
+const PAIR_PROBABILITY: f32 = 0.0; // Chance that an emitted particle comes as a jointed pair (a dumbbell).
+const PAIR_SPACING: f32 = 0.8; // Distance between the centers of the two halves of a dumbbell.
+const SURFACE_OFFSET: f32 = 0.05; // How far off a mesh surface (along its normal) particles spawn.
+const TIME_SCALE_RANGE: (f32, f32) = (0.125, 2.0); // Slowest and fastest the simulation can be run at.
+const PHYSICS_MAX_DT: f32 = 1.0 / 60.0; // Longest physics step at normal speed (Rapier's default).
+const HEADLESS_FPS: f64 = 60.0; // Frame rate headless runs are paced at.
+const CONFIG_POLL_SECS: f32 = 1.0; // How often to check the config file for changes.
+const SPIRAL_SPEED: f32 = std::f32::consts::PI; // Radians per second the spiral pattern turns on its own.
 
 fn main() {
-    // Create the bevy 'app' and add all of the plugins/systems.
-    App::new()
-        .insert_resource(Msaa::Off)
-        .insert_resource(AmbientLight {
-            color: Color::WHITE,
-            brightness: 1.0 / 5.0f32,
-        })
-        .insert_resource(PointLightShadowMap { size: 4096 })
-        .add_plugins(DefaultPlugins)
-        .add_plugins(FrameTimeDiagnosticsPlugin {})
+    let args = Args::parse();
+    let headless = args.headless;
+    let frames = args.frames;
+
+    // Create the bevy 'app' and add all of the plugins/systems.  Without a window there's
+    // nothing to render, so headless runs only get the plugins the simulation itself needs.
+    let mut app = App::new();
+    if headless {
+        app.add_plugins((
+            MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(Duration::from_secs_f64(
+                1.0 / HEADLESS_FPS,
+            ))),
+            TransformPlugin,
+            HierarchyPlugin,
+            InputPlugin,
+            DiagnosticsPlugin,
+            AssetPlugin::default(),
+            ScenePlugin,
+        ))
+        .init_asset::<Mesh>()
+        .init_asset::<StandardMaterial>();
+    } else {
+        app.insert_resource(Msaa::Off)
+            .insert_resource(PointLightShadowMap { size: 4096 })
+            .add_plugins(DefaultPlugins)
+            .add_plugins(EguiPlugin);
+    }
+
+    // The simulation: spawning, despawning, physics and the keyboard controls.
+    app.add_plugins(FrameTimeDiagnosticsPlugin {})
         .add_plugins(RapierPhysicsPlugin::<NoUserData>::default())
+        .init_resource::<SpawnStats>()
+        .add_event::<ParticleSpawned>()
+        .add_event::<ParticleDespawned>()
+        .init_resource::<DespawnStats>()
+        .init_resource::<Wind>()
+        .insert_resource(ParticleRng::new(args.seed))
+        .insert_resource(args)
+        .init_resource::<Attractor>()
+        .init_resource::<Paused>()
+        .init_resource::<TimeScale>()
+        .init_resource::<ActivePreset>()
+        .init_resource::<AdaptiveRate>()
+        .init_resource::<PointerOverUi>()
+        .init_resource::<StatusNotice>()
         .add_systems(Startup, setup)
-        .add_systems(Update, (spawn_particles, despawn_particles))
-        .add_systems(Update, bevy::window::close_on_esc)
-        // FPS display
-        .add_systems(Startup, setup_fps_counter)
-        .add_systems(Update, (fps_text_update_system, fps_counter_showhide))
-        //
-        .run();
+        // All the spawners draw from the particle pool, so make sure each one sees the
+        // entities the previous ones already took.  Evicting (when over the cap) has to see
+        // every new particle too.
+        .add_systems(
+            Update,
+            (
+                spawn_particles,
+                apply_deferred,
+                burst_particles,
+                apply_deferred,
+                click_spawn_particles.run_if(pointer_free),
+                apply_deferred,
+                splash_particles,
+                record_previous_velocity,
+                apply_deferred,
+                evict_oldest_particles,
+            )
+                .chain()
+                .run_if(running),
+        )
+        .add_systems(Update, log_spawn_rate.after(click_spawn_particles))
+        .add_systems(
+            Update,
+            (
+                count_despawns
+                    .after(despawn_particles)
+                    .after(clear_particles),
+                print_despawn_stats.after(count_despawns),
+            ),
+        )
+        .add_systems(
+            Update,
+            (
+                despawn_particles
+                    .after(evict_oldest_particles)
+                    .run_if(running),
+                despawn_on_ground_hit.before(despawn_particles),
+                despawn_sleeping.before(despawn_particles).run_if(running),
+                despawn_after_bounces.before(despawn_particles),
+                despawn_below_kill_plane
+                    .before(despawn_particles)
+                    .run_if(running),
+                color_particles_by_speed,
+                color_particles_by_height,
+                color_particles_by_age,
+                update_particle_glow,
+                update_particle_shadows,
+                fade_particles,
+                shrink_particles,
+                apply_wind,
+                apply_attractor.after(apply_wind),
+            ),
+        )
+        // Keyboard controls
+        .add_systems(
+            Update,
+            (
+                adjust_spread_angle,
+                cycle_emission_shape,
+                adjust_spawn_rate,
+                toggle_emission_mode,
+                cycle_particle_coloring,
+                cycle_death_effect,
+                print_spawn_stats,
+                toggle_particle_shape,
+                toggle_spawn_ramp,
+                toggle_attractor,
+                flip_attractor,
+                explode_at_cursor,
+                toggle_emission,
+                toggle_pause,
+                toggle_despawn_on_ground_hit,
+                toggle_stagger_batches,
+                toggle_emitter_gizmos,
+                toggle_emission_pattern,
+                toggle_adaptive_rate,
+                toggle_spawn_preset,
+            ),
+        )
+        .add_systems(Update, adjust_spawn_count.before(spawn_particles))
+        .add_systems(Update, select_preset.before(spawn_particles))
+        .add_systems(Update, clear_particles.before(spawn_particles))
+        // Particle trails
+        .add_systems(Update, (record_trails.run_if(running), toggle_trails))
+        .add_systems(Update, reload_config_file)
+        // Physics controls
+        .add_systems(
+            Update,
+            (
+                adjust_gravity,
+                adjust_wind,
+                adjust_restitution,
+                adjust_friction,
+                adjust_time_scale,
+            ),
+        )
+        .add_systems(
+            Update,
+            (
+                run_emission_script.before(spawn_particles),
+                toggle_emission_script,
+            ),
+        )
+        .add_systems(Update, orbit_emitters.before(spawn_particles))
+        // Collision counter
+        .init_resource::<CollisionStats>()
+        .add_systems(Update, count_ground_collisions);
+
+    // Everything that draws or needs a window: gizmos, the UI, the camera and the control panel.
+    if !headless {
+        app.add_systems(Update, (place_attractor, steer_nozzle, draw_emitter_gizmos))
+            .add_systems(Update, draw_trails.after(record_trails))
+            // Control panel
+            .add_systems(Update, control_panel)
+            .add_systems(Update, bevy::window::close_on_esc)
+            .add_systems(Update, take_screenshot)
+            // Camera controls
+            .init_resource::<CameraMode>()
+            .init_resource::<CameraZoom>()
+            .add_systems(
+                Update,
+                (
+                    orbit_camera.run_if(pointer_free),
+                    zoom_camera.run_if(pointer_free),
+                    fly_camera,
+                    toggle_camera_mode,
+                ),
+            )
+            // FPS display
+            .add_systems(Startup, setup_fps_counter)
+            .add_systems(Update, (fps_text_update_system, fps_counter_showhide))
+            // Status line
+            .add_systems(Startup, setup_status_text)
+            .add_systems(Update, update_status_text)
+            // Particle count display
+            .add_systems(Startup, setup_particle_counter)
+            .add_systems(Update, particle_count_update_system)
+            // Ground texture
+            .add_systems(Update, fall_back_to_flat_ground)
+            // Flashes where particles are retired
+            .add_systems(
+                Update,
+                (spawn_flashes.after(despawn_particles), update_flashes),
+            )
+            // Lighting: ambient light controls, and the day/night cycle (when lit by the sun)
+            .add_systems(
+                Update,
+                (
+                    adjust_ambient_light,
+                    update_ambient_light.after(adjust_ambient_light),
+                    move_sun.after(adjust_ambient_light),
+                    update_fog,
+                ),
+            )
+            // Collision counter display
+            .add_systems(Startup, setup_collision_counter)
+            .add_systems(
+                Update,
+                collision_counter_update_system.after(count_ground_collisions),
+            );
+    }
+
+    if let Some(frames) = frames {
+        app.add_systems(
+            Last,
+            move |count: Res<FrameCount>, mut exit: EventWriter<AppExit>| {
+                if count.0 >= frames {
+                    exit.send(AppExit);
+                }
+            },
+        );
+    }
+
+    app.run();
 }
 
 // ParticleMarker - this component marks an entity as a particle.  Used for querying inside systems.
 #[derive(Component)]
 struct ParticleMarker;
 
-// ExpireTime - a component that denotes the time an entity should live before despawning.
+// PooledParticle - marks an expired particle that has been parked (hidden, with its physics
+// disabled) so spawn_particle can reuse the entity instead of allocating a new one.
 #[derive(Component)]
-struct ExpireTime(Instant);
-impl Default for ExpireTime {
+struct PooledParticle;
+
+// PrivateMaterial - marks a particle that owns its material (rather than sharing one), so
+// systems are free to change its color or alpha.
+#[derive(Component)]
+struct PrivateMaterial;
+
+// ParticleScale - a component holding the scale a particle was spawned with (its radius relative
+// to the shared sphere mesh), so effects that change the scale know what to start from.
+#[derive(Component)]
+struct ParticleScale(f32);
+
+// ExpireTime - a component that denotes the time (in seconds of elapsed app time, see
+// Time::elapsed_seconds) at which an entity should despawn.
+#[derive(Component)]
+struct ExpireTime(f32);
+
+// SpawnTime - a component holding the time (in seconds of elapsed app time) a particle was
+// (notionally) spawned at, so its age can be compared with its whole lifetime.
+#[derive(Component)]
+struct SpawnTime(f32);
+
+// AsleepSince - a component holding the time (in seconds of elapsed app time) a particle's rigid
+// body went to sleep.  It's removed again if the body wakes up.
+#[derive(Component)]
+struct AsleepSince(f32);
+
+// RetireCause - a component noting why a particle is going to be retired: it's Expired unless
+// something brought its expire time forward.
+#[derive(Component, Default)]
+struct RetireCause(DespawnCause);
+
+// BounceCount - a component counting how many times a particle has hit the ground.
+#[derive(Component, Default)]
+struct BounceCount(u32);
+
+// Emitter - a component that turns an entity into a particle source.  New particles are
+// spawned relative to the entity's Transform.
+#[derive(Component)]
+struct Emitter {
+    // How much time should elapse between batches of new particles.
+    spawn_delta: Duration,
+    // Number of particles spawned per batch.
+    spawn_count: usize,
+    // Distribution of the initial velocity vector magnitude of new particles.
+    speed: SpeedDistribution,
+    // Number of seconds until each particle despawns.
+    expire_time: f32,
+    // When (in elapsed seconds) this emitter should spawn its next batch.
+    next_spawn: f32,
+    // Number of particles spawned per second in continuous mode.
+    rate: f32,
+    // Fractional particles carried over between frames in continuous mode.
+    accumulator: f32,
+    // Optional ramp that overrides 'rate' in continuous mode.
+    ramp: Option<SpawnRamp>,
+    // Where around the emitter new particles appear, and which way they're launched.
+    shape: EmitterShape,
+    // How much of the emitter's own velocity new particles inherit (0 = none, 1 = all of it).
+    inherit_factor: f32,
+    // The emitter's velocity, measured by differencing its position between frames.
+    velocity: Vec3,
+    // Where the emitter was last frame (None until it's been seen once).
+    last_position: Option<Vec3>,
+    // Azimuth (in radians, around the emission axis) of the next particle in the spiral pattern.
+    spiral_phase: f32,
+    // Mesh for this emitter's particles, instead of the configuration's (None = use that).
+    mesh: Option<Handle<Mesh>>,
+    // Material for this emitter's particles, instead of the configuration's (None = use that).
+    material: Option<Handle<StandardMaterial>>,
+}
+
+impl Default for Emitter {
+    // Creates an emitter with the built-in spawn settings (ignoring any config file or
+    // command line overrides).
     fn default() -> Self {
-        ExpireTime(Instant::now())
+        Emitter {
+            spawn_delta: Duration::from_millis(PARTICLE_RESPAWN_TIME_MS),
+            spawn_count: SPAWN_COUNT,
+            speed: SpeedDistribution::Constant(INITIAL_VELOCITY),
+            expire_time: PARTICLE_EXPIRE_TIME_SECS,
+            next_spawn: 0.0,
+            rate: SPAWN_RATE,
+            accumulator: 0.0,
+            ramp: None,
+            shape: EmitterShape::Point,
+            inherit_factor: 0.0,
+            velocity: Vec3::ZERO,
+            last_position: None,
+            spiral_phase: 0.0,
+            mesh: None,
+            material: None,
+        }
     }
 }
 
-// Configuration - global resource containing system wide data.
+impl Emitter {
+    // Creates an emitter that uses the spawn settings from the configuration.
+    fn new(configuration: &Configuration) -> Self {
+        Emitter {
+            spawn_delta: configuration.spawn_delta,
+            spawn_count: configuration.spawn_count,
+            speed: configuration.speed_distribution,
+            expire_time: configuration.particle_expire_time,
+            rate: configuration.spawn_rate,
+            ..default()
+        }
+    }
+}
+
+// EmitterBundle - everything an emitter entity needs, with builder methods for the commonly
+// changed settings, e.g.
+//   commands.spawn(EmitterBundle::new().rate(200.0).speed(3.0).at(Vec3::new(0.0, 5.0, 0.0)));
+#[derive(Bundle, Default)]
+struct EmitterBundle {
+    emitter: Emitter,
+    transform: TransformBundle,
+}
+
+impl EmitterBundle {
+    // Creates an emitter bundle at the origin with the built-in spawn settings.
+    fn new() -> Self {
+        EmitterBundle::default()
+    }
+
+    // Creates an emitter bundle at the origin that uses the spawn settings from the
+    // configuration.
+    fn from_configuration(configuration: &Configuration) -> Self {
+        EmitterBundle {
+            emitter: Emitter::new(configuration),
+            ..EmitterBundle::new()
+        }
+    }
+
+    // Sets the number of particles spawned per second in continuous mode.
+    fn rate(mut self, rate: f32) -> Self {
+        self.emitter.rate = rate;
+        self
+    }
+
+    // Sets the number of particles spawned per batch in batch mode.
+    fn count(mut self, count: usize) -> Self {
+        self.emitter.spawn_count = count;
+        self
+    }
+
+    // Sets where around the emitter new particles appear, and which way they're launched.
+    fn shape(mut self, shape: EmitterShape) -> Self {
+        self.emitter.shape = shape;
+        self
+    }
+
+    // Sets the average launch speed, keeping the spread of the current distribution.
+    fn speed(mut self, speed: f32) -> Self {
+        self.emitter.speed = self.emitter.speed.with_mean(speed);
+        self
+    }
+
+    // Sets how long each particle lives.
+    fn lifetime(mut self, lifetime: Duration) -> Self {
+        self.emitter.expire_time = lifetime.as_secs_f32();
+        self
+    }
+
+    // Places the emitter at 'position'.
+    fn at(mut self, position: Vec3) -> Self {
+        self.transform = TransformBundle::from(Transform::from_translation(position));
+        self
+    }
+}
+
+// SpawnRamp - a continuous spawn rate that changes linearly from 'initial_rate' to 'target_rate'
+// over 'duration' seconds, then holds at 'target_rate'.
+#[derive(Clone, Copy, Debug)]
+struct SpawnRamp {
+    // Rate (particles per second) at the start of the ramp.
+    initial_rate: f32,
+    // Rate (particles per second) at the end of the ramp.
+    target_rate: f32,
+    // How long (in seconds) the ramp takes.
+    duration: f32,
+    // When (in elapsed seconds) the ramp started.
+    start_time: f32,
+}
+
+impl SpawnRamp {
+    // Returns the spawn rate at the elapsed time 'now'.
+    fn rate_at(&self, now: f32) -> f32 {
+        let t = if self.duration > 0.0 {
+            ((now - self.start_time) / self.duration).clamp(0.0, 1.0)
+        } else {
+            1.0
+        };
+        self.initial_rate + (self.target_rate - self.initial_rate) * t
+    }
+}
+
+// Orbit - moves an entity (usually an emitter) around a horizontal circle.
+#[derive(Component)]
+struct Orbit {
+    // The center of the circle.
+    center: Vec3,
+    // The radius of the circle.
+    radius: f32,
+    // How fast the entity travels around the circle (in radians per second).
+    angular_speed: f32,
+}
+
+// EmitterShape - the region around an emitter that new particles are spawned from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum EmitterShape {
+    // Spawn anywhere within +/- max_spawn_offset (in X, Z) of the emitter, spawn_height above
+    // it, and launch inside the emission cone.
+    Point,
+    // Spawn uniformly on the surface of a sphere centered on the emitter and launch radially
+    // outward.
+    Sphere { radius: f32 },
+    // Spawn on a circle in the XZ plane around the emitter and launch inside the emission cone.
+    Ring { radius: f32 },
+    // Spawn uniformly inside a disc in the XZ plane around the emitter and launch inside the
+    // emission cone.
+    Disc { radius: f32 },
+    // Spawn uniformly inside an axis-aligned box centered on the emitter and launch inside the
+    // emission cone.  (The original, off-center spawn region is a Box with half extents
+    // (1.0, 0.5, 1.0) on an emitter at (2.0, 4.5, 2.0).)
+    Box { half_extents: Vec3 },
+    // Spawn uniformly over the surface of the mesh held by the SurfaceSampler resource (placed
+    // at the emitter) and launch along the normal of the triangle the particle starts on.
+    Surface,
+}
+
+// SpeedDistribution - how the initial speed of new particles is chosen.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+enum SpeedDistribution {
+    // Every particle launches at exactly this speed.
+    Constant(f32),
+    // Speeds follow a normal distribution (clamped at zero).
+    Normal { mean: f32, std_dev: f32 },
+}
+
+impl SpeedDistribution {
+    // The average speed.
+    fn mean(&self) -> f32 {
+        match *self {
+            SpeedDistribution::Constant(speed) => speed,
+            SpeedDistribution::Normal { mean, .. } => mean,
+        }
+    }
+
+    // Returns the same distribution moved to average 'mean' (with the same spread).
+    fn with_mean(&self, mean: f32) -> Self {
+        match *self {
+            SpeedDistribution::Constant(_) => SpeedDistribution::Constant(mean),
+            SpeedDistribution::Normal { std_dev, .. } => {
+                SpeedDistribution::Normal { mean, std_dev }
+            }
+        }
+    }
+
+    // Draws a speed from the distribution.
+    fn sample(&self, rng: &mut impl Rng) -> f32 {
+        match *self {
+            SpeedDistribution::Constant(speed) => speed,
+            SpeedDistribution::Normal { mean, std_dev } => {
+                // Box-Muller transform.  1 - random() is in (0, 1] so the log stays finite.
+                let u1 = 1.0 - rng.gen::<f32>();
+                let u2 = rng.gen::<f32>();
+                let z = (-2.0 * u1.ln()).sqrt() * (std::f32::consts::TAU * u2).cos();
+                (mean + std_dev * z).max(0.0)
+            }
+        }
+    }
+
+    // Returns the same distribution with all speeds multiplied by 'factor'.
+    fn scaled(&self, factor: f32) -> Self {
+        match *self {
+            SpeedDistribution::Constant(speed) => SpeedDistribution::Constant(speed * factor),
+            SpeedDistribution::Normal { mean, std_dev } => SpeedDistribution::Normal {
+                mean: mean * factor,
+                std_dev: std_dev * factor,
+            },
+        }
+    }
+}
+
+// ParticleSpawned - an event sent for every particle as it's spawned (or reused from the pool),
+// so other systems can react to new particles, e.g. by adding components to them.  Systems that
+// read it should run after the spawners so the entity is there by the time their commands run.
+#[derive(Event)]
+struct ParticleSpawned {
+    // The new particle.
+    entity: Entity,
+    // Where it was spawned.
+    position: Vec3,
+    // Its initial (linear) velocity.
+    velocity: Vec3,
+}
+
+// DespawnCause - why a particle was retired.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+enum DespawnCause {
+    // It reached the end of its life.
+    #[default]
+    Expired,
+    // It touched the ground with despawn_on_ground_hit on.
+    HitGround,
+    // It used up its bounces (Configuration::max_bounces).
+    Bounced,
+    // It came to rest for longer than Configuration::sleep_grace_period.
+    Slept,
+    // It fell below the kill plane.
+    FellOffWorld,
+    // It was evicted to make room, with the EvictOldest cap policy.
+    Evicted,
+    // Everything was cleared (Delete).
+    Cleared,
+}
+
+impl DespawnCause {
+    // Every cause, in the order they're listed in.
+    const ALL: [DespawnCause; 7] = [
+        DespawnCause::Expired,
+        DespawnCause::HitGround,
+        DespawnCause::Bounced,
+        DespawnCause::Slept,
+        DespawnCause::FellOffWorld,
+        DespawnCause::Evicted,
+        DespawnCause::Cleared,
+    ];
+}
+
+// ParticleDespawned - an event sent for every particle as it's retired (to the pool or for
+// good), in the same frame, saying why.  By the time it's read the entity may be gone or
+// reused, so it carries what consumers need to know.
+#[derive(Event)]
+struct ParticleDespawned {
+    // The particle that was retired.
+    entity: Entity,
+    // Where it was at the time.
+    position: Vec3,
+    // Why it was retired.
+    cause: DespawnCause,
+}
+
+// DespawnStats - a resource counting retired particles by cause, as an example of reacting to
+// ParticleDespawned events ('D' prints them).
+#[derive(Resource, Default)]
+struct DespawnStats(bevy::utils::HashMap<DespawnCause, u64>);
+
+// SpawnStats - a resource holding the initial speed statistics of the last emitter batch, so the
+// speed distribution can be checked ('I' prints them).
+#[derive(Resource, Default)]
+struct SpawnStats {
+    min_speed: f32,
+    max_speed: f32,
+    mean_speed: f32,
+    count: usize,
+}
+
+impl SpawnStats {
+    // Replaces the statistics with those of the given batch of speeds.
+    fn record(&mut self, speeds: &[f32]) {
+        if speeds.is_empty() {
+            return;
+        }
+        self.min_speed = speeds.iter().copied().fold(f32::INFINITY, f32::min);
+        self.max_speed = speeds.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        self.mean_speed = speeds.iter().sum::<f32>() / speeds.len() as f32;
+        self.count = speeds.len();
+    }
+}
+
+// SpawnPreset - where the emitters' particles come from and which way they go.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SpawnPreset {
+    // Every emitter launches particles upwards from its own shape, as configured.
+    Fountain,
+    // Particles fall from RAIN_HEIGHT anywhere over the ground, mostly straight down, and live
+    // for RAIN_EXPIRE_TIME_SECS (they reach the ground quickly).  The emitters still set the
+    // timing.
+    Rain,
+}
+
+// EmissionPattern - how the launch directions of successive particles relate to each other.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum EmissionPattern {
+    // Each particle's direction is drawn independently from the emission shape.
+    Random,
+    // Particles leave along the rim of the emission cone, each one turned a little further
+    // around the axis than the last, like a lawn sprinkler.  Emitters that launch radially
+    // ignore it.
+    Spiral,
+}
+
+// EmissionShape - the distribution of launch directions (around Configuration::emission_axis)
+// used by the emitter shapes that don't launch radially.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum EmissionShape {
+    // Straight along the emission axis.
+    Point,
+    // Uniformly (by solid angle) inside the cone with a full apex angle of
+    // Configuration::spread_angle, i.e. a half-angle of spread_angle / 2.
+    Cone,
+    // Towards a uniformly chosen point on a disc of the given radius, one unit along the
+    // emission axis.  Directions bunch up towards the rim of the cone this implies.
+    Disk { radius: f32 },
+    // Uniformly in every direction.
+    Sphere,
+    // The original sampling, kept for comparison: a random point within +/- LEGACY_JITTER across
+    // the axis, one unit along it, normalized.  Directions bunch up towards the axis and the
+    // corners of that square rather than spreading evenly.
+    Legacy,
+}
+
+// EmissionMode - how emitters decide when to spawn particles.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum EmissionMode {
+    // Spawn 'spawn_count' particles every 'spawn_delta' (produces visible pulses).
+    Batch,
+    // Spawn a steady stream of 'rate' particles per second.
+    Continuous,
+    // Like Continuous, but the rate swells and ebbs sinusoidally, sending out waves of particles.
+    Pulsed,
+}
+
+// ParticleColoring - how particles are colored.  Anything but Uniform gives every particle its
+// own material instance (allocated in spawn_particle) so its color can change independently.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ParticleColoring {
+    // All particles share Configuration::particle_material.
+    Uniform,
+    // Blue at SPEED_COLOR_MIN m/s through to red at SPEED_COLOR_MAX m/s.
+    Speed,
+    // Configuration::low_height_color on the ground through to Configuration::high_height_color
+    // at HEIGHT_COLOR_MAX.
+    Height,
+    // Each particle gets a random hue, picked from Configuration::palette_materials so no
+    // material has to be created per particle.
+    RandomHue,
+    // Configuration::start_color when a particle spawns through to Configuration::end_color
+    // when it expires.
+    Age,
+}
+
+// DeathEffect - what particles do as they approach their expire time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum DeathEffect {
+    // Particles simply disappear when they expire.
+    None,
+    // Particles fade out (alpha 1 -> 0) over the last Configuration::fade_out_time seconds of
+    // their life.
+    Fade,
+    // Particles shrink (scale 1 -> 0) over the last Configuration::shrink_time seconds of their
+    // life.
+    Shrink,
+}
+
+// PairJoint - the joint holding the two halves of a dumbbell together.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+enum PairJoint {
+    // The halves can swivel freely around the midpoint between them.
+    Spherical,
+    // The halves are welded together.
+    Fixed,
+}
+
+// Lighting - what lights the scene.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+enum Lighting {
+    // A single point light off to one side, high above the scene.
+    Point,
+    // A directional light playing the sun, which rises and sets over Configuration::day_length
+    // seconds, with the ambient light and sky changing to match.
+    Sun,
+}
+
+// CapPolicy - what happens when spawning would take the population over
+// Configuration::max_particles.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+enum CapPolicy {
+    // Spawners hold back until enough particles have expired.
+    RefuseSpawn,
+    // Spawners carry on, and the particles closest to expiring are retired to make room.
+    EvictOldest,
+}
+
+// PairPartner - links each half of a dumbbell to the other, so they're retired together and no
+// joint is left pointing at a parked (or despawned) particle.
+#[derive(Component)]
+struct PairPartner(Entity);
+
+// ParticleShape - the geometry (mesh and collider) of new particles.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ParticleShape {
+    Sphere,
+    // Cubes make the particles' spin visible.
+    Cube,
+}
+
+// Args - a resource holding the command-line arguments.  The spawn settings given here override
+// both the defaults and the config file.
+#[derive(Parser, Resource, Debug)]
+#[command(about = "Particles bouncing around a small physics scene")]
+struct Args {
+    /// Seed for the particles' random number generator (random if not given).
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Number of particles each emitter spawns per batch.
+    #[arg(long)]
+    spawn_count: Option<usize>,
+    /// Time (in milliseconds) between batches.
+    #[arg(long)]
+    spawn_delay_ms: Option<u64>,
+    /// Number of seconds until each particle despawns.
+    #[arg(long)]
+    expire_secs: Option<f32>,
+    /// Radius of every particle (instead of a random one from the radius range).
+    #[arg(long)]
+    radius: Option<f32>,
+    /// Mean initial speed of new particles.
+    #[arg(long)]
+    initial_velocity: Option<f32>,
+    /// Upper bound on the number of live particles.
+    #[arg(long)]
+    max_particles: Option<usize>,
+    /// Run the simulation without a window (or any rendering), e.g. for automated tests.
+    #[arg(long)]
+    headless: bool,
+    /// Quit after this many frames.
+    #[arg(long)]
+    frames: Option<u32>,
+    /// Render in HDR with bloom, so glowing particles (see emissive_strength in the config file)
+    /// light up their surroundings.
+    #[arg(long)]
+    bloom: bool,
+}
+
+impl Args {
+    // Copies the settings that were given into the configuration.
+    fn apply(&self, configuration: &mut Configuration) {
+        if let Some(spawn_count) = self.spawn_count {
+            configuration.spawn_count = spawn_count;
+        }
+        if let Some(spawn_delay_ms) = self.spawn_delay_ms {
+            configuration.spawn_delta = Duration::from_millis(spawn_delay_ms);
+        }
+        if let Some(expire_secs) = self.expire_secs {
+            configuration.set_particle_expire_time(expire_secs);
+        }
+        if let Some(radius) = self.radius {
+            configuration.particle_radius_range = (radius, radius);
+        }
+        if let Some(initial_velocity) = self.initial_velocity {
+            let mean = configuration.speed_distribution.mean();
+            configuration.speed_distribution = if mean > 0.0 {
+                configuration
+                    .speed_distribution
+                    .scaled(initial_velocity / mean)
+            } else {
+                SpeedDistribution::Constant(initial_velocity)
+            };
+        }
+        if let Some(max_particles) = self.max_particles {
+            configuration.max_particles = max_particles;
+        }
+    }
+}
+
+// ParticleRng - a resource holding the random number generator all particle spawning draws
+// from.  It's seeded at startup, so a run can be replayed by passing the same '--seed'.  That
+// makes the spawned positions and velocities repeatable; what the physics then does with them is
+// only repeatable as far as Rapier itself is (same build, same platform, same frame timing -
+// the 'parallel' feature and a variable frame rate both get in the way).
+#[derive(Resource)]
+struct ParticleRng(rngs::StdRng);
+
+impl ParticleRng {
+    // Seeds the generator with 'seed', or randomly if there isn't one.  The seed is logged
+    // either way.
+    fn new(seed: Option<u64>) -> Self {
+        let seed = seed.unwrap_or_else(random::<u64>);
+        info!("Random seed: {} (replay with --seed {})", seed, seed);
+        ParticleRng(rngs::StdRng::seed_from_u64(seed))
+    }
+}
+
+// AdaptiveRate - a resource holding the fraction of their normal rate the emitters currently
+// spawn at when the adaptive spawn rate is switched on.
+#[derive(Resource)]
+struct AdaptiveRate(f32);
+
+impl Default for AdaptiveRate {
+    fn default() -> Self {
+        AdaptiveRate(1.0)
+    }
+}
+
+// TimeScale - a resource holding how fast the simulation runs relative to real time (below 1 is
+// slow motion).
+#[derive(Resource)]
+struct TimeScale(f32);
+
+impl Default for TimeScale {
+    fn default() -> Self {
+        TimeScale(1.0)
+    }
+}
+
+// Paused - a resource that's true while the whole simulation is frozen.
+#[derive(Resource, Default)]
+struct Paused(bool);
+
+// running - a run condition for the systems that must stand still while paused.
+fn running(paused: Res<Paused>) -> bool {
+    !paused.0
+}
+
+// PointerOverUi - a resource that's true while the mouse is busy with the control panel, so
+// clicks and drags there don't also move the camera or spawn particles.
+#[derive(Resource, Default)]
+struct PointerOverUi(bool);
+
+// pointer_free - a run condition for the systems that use the mouse in the scene.
+fn pointer_free(over_ui: Res<PointerOverUi>) -> bool {
+    !over_ui.0
+}
+
+// EmissionPhase - one step of an EmissionScript: the spawn settings to use for 'duration'
+// seconds.  A phase with a zero rate or count stops emission.
+#[derive(Clone, Debug)]
+struct EmissionPhase {
+    // Shown in the log when the phase starts.
+    name: &'static str,
+    // How long (in seconds) the phase lasts.
+    duration: f32,
+    // Particles per second in continuous mode.
+    spawn_rate: f32,
+    // Particles per batch in batch mode.
+    spawn_count: usize,
+    // Mean initial speed of new particles.
+    speed: f32,
+}
+
+// EmissionScript - a resource holding a sequence of emission phases, for repeatable demos.  While
+// it's enabled, run_emission_script writes each phase's settings into the configuration in turn.
+#[derive(Resource)]
+struct EmissionScript {
+    // Whether the script is running ('O' toggles it).
+    enabled: bool,
+    // The phases, in order.
+    phases: Vec<EmissionPhase>,
+    // Whether to start over after the last phase (otherwise it holds).
+    looping: bool,
+    // The phase currently applied (None until the first one has been).
+    current: Option<usize>,
+    // When (in elapsed seconds) the current phase started.
+    phase_start: f32,
+}
+
+impl EmissionScript {
+    // The demo script: a gentle warmup, the configured steady stream, a short burst and a pause.
+    fn demo(configuration: &Configuration) -> Self {
+        let speed = configuration.speed_distribution.mean();
+        EmissionScript {
+            enabled: true,
+            phases: vec![
+                EmissionPhase {
+                    name: "warmup",
+                    duration: 3.0,
+                    spawn_rate: configuration.spawn_rate / 4.0,
+                    spawn_count: (configuration.spawn_count / 4).max(1),
+                    speed: speed / 2.0,
+                },
+                EmissionPhase {
+                    name: "steady",
+                    duration: 10.0,
+                    spawn_rate: configuration.spawn_rate,
+                    spawn_count: configuration.spawn_count,
+                    speed,
+                },
+                EmissionPhase {
+                    name: "burst",
+                    duration: 1.0,
+                    spawn_rate: configuration.spawn_rate * 5.0,
+                    spawn_count: configuration.spawn_count * 5,
+                    speed: speed * 2.0,
+                },
+                EmissionPhase {
+                    name: "stop",
+                    duration: 4.0,
+                    spawn_rate: 0.0,
+                    spawn_count: 0,
+                    speed,
+                },
+            ],
+            looping: true,
+            current: None,
+            phase_start: 0.0,
+        }
+    }
+}
+
+// Wind - a resource holding the force that blows on every particle.  Zero means no wind.
+#[derive(Resource, Default)]
+struct Wind(Vec3);
+
+// Attractor - a resource describing a point that pulls particles towards it (or, with a negative
+// strength, pushes them away) with an inverse square force.
 #[derive(Resource)]
+struct Attractor {
+    // Whether the attractor is active ('T' toggles it).
+    enabled: bool,
+    // Where the attractor is (middle-clicking the ground moves it there).
+    position: Vec3,
+    // Force at a distance of 1.  Negative values repel ('Y' flips the sign).
+    strength: f32,
+}
+
+impl Default for Attractor {
+    fn default() -> Self {
+        Attractor {
+            enabled: false,
+            position: Vec3::new(0.0, GROUND_HEIGHT + 2.0, 0.0),
+            strength: ATTRACTOR_STRENGTH,
+        }
+    }
+}
+
+// Configuration - global resource containing system wide data.
+#[derive(Resource, Clone)]
 struct Configuration {
     // The mesh for the particle.  Created once at setup and reused for all subsequent particles.
     sphere_mesh: Handle<Mesh>,
+    // The cube mesh for the particle.  Also created once at setup.
+    cube_mesh: Handle<Mesh>,
+    // Which of the meshes (and matching collider) new particles use ('K' toggles it).
+    particle_shape: ParticleShape,
+    // Max angular speed (in radians per second) of new particles.  Zero spawns them without spin.
+    max_angular_speed: f32,
     // The material for the particle.  Created once at setup and reused for all subsequent particles.
     particle_material: Handle<StandardMaterial>,
-    // Used to determine how much time should elapse before spawning new particles.
+    // Materials with evenly spaced hues, used when coloring particles randomly.  Also created once
+    // at setup.
+    palette_materials: Vec<Handle<StandardMaterial>>,
+    // The mesh flashes are drawn with, and their materials from opaque through to transparent
+    // (so a flash can fade by swapping materials rather than needing one of its own).  Also
+    // created once at setup.
+    flash_mesh: Handle<Mesh>,
+    flash_materials: Vec<Handle<StandardMaterial>>,
+    // Radius of the shared sphere mesh and of the particle collider (both built from it at setup).
+    // Particles are scaled from this to their actual radius.
+    particle_radius: f32,
+    // Range (min, max) each new particle's radius is picked from.
+    particle_radius_range: (f32, f32),
+    // How much time should elapse between batches of new particles.
     spawn_delta: Duration,
+    // Number of particles to spawn when it's time to do so.
+    spawn_count: usize,
+    // Number of seconds until each particle despawns.
+    particle_expire_time: f32,
+    // Range (min, max) in seconds each particle's lifetime is drawn from, so they don't all
+    // vanish in waves, e.g. (8, 12) around a particle_expire_time of 10.  Equal ends give every
+    // particle exactly the same lifetime.  Emitters with a lifetime of their own (and rain) scale
+    // it by their lifetime relative to particle_expire_time, and changing particle_expire_time
+    // (see set_particle_expire_time) scales it along.
+    lifetime_range: (f32, f32),
+    // Max offset (+/- in X, Z) of new particle location.  Clamped to GROUND_RADIUS.
+    max_spawn_offset: f32,
+    // Height of new particles above their emitter.
+    spawn_height: f32,
+    // Distribution of the initial velocity vector magnitude of new particles.  Every particle
+    // launches at INITIAL_VELOCITY unless the config file asks for a Normal distribution.
+    speed_distribution: SpeedDistribution,
+    // Whether the emitters are spawning particles at all (Enter toggles it).
+    emitting: bool,
+    // Whether emitters spawn in batches, as a continuous stream or in waves ('M' cycles through
+    // the options).
+    emission_mode: EmissionMode,
+    // Max number of batches an emitter spawns at once to make up for intervals missed during a
+    // long frame.
+    max_catch_up_batches: usize,
+    // Whether batch mode spreads each batch out along the time since the last one, instead of
+    // spawning it all in one clump ('J' toggles it).
+    stagger_batches: bool,
+    // Number of particles spawned per second by each emitter in continuous mode.
+    spawn_rate: f32,
+    // How far the rate swings above and below its base in pulsed mode (0 = not at all, 1 = down
+    // to nothing and up to double).
+    pulse_amplitude: f32,
+    // Seconds per wave in pulsed mode (0 = no waves).
+    pulse_period: f32,
+    // How particles are colored ('C' cycles through the options).
+    coloring: ParticleColoring,
+    // What particles do as they approach their expire time ('V' cycles through the options).
+    death_effect: DeathEffect,
+    // Seconds before expiring that particles start to fade out with DeathEffect::Fade (0 = they
+    // simply disappear, as with DeathEffect::None).
+    fade_out_time: f32,
+    // Seconds before expiring that particles start to shrink with DeathEffect::Shrink (0 = they
+    // simply disappear, as with DeathEffect::None).
+    shrink_time: f32,
+    // Bounciness of new particles (0 = no bounce, 1 = no energy lost).  Particles combine it with
+    // whatever they hit using the max rule, so the (unbouncy) ground doesn't cancel it out.
+    restitution: f32,
+    // Friction coefficient of new particles and the ground.  Both sides of a contact use the
+    // average rule (Rapier's default), so the two values are blended.
+    friction: f32,
+    // Whether new particles collide with each other.  Switched off, they only collide with the
+    // ground (and the other fixed scenery) and pass through one another: instead of piling up
+    // they spread out into a single layer on the ground, and dense fountains get much cheaper
+    // as the solver no longer has particle contacts to resolve.
+    particle_collisions: bool,
+    // Whether to draw each emitter's origin, launch direction and spawn region (F1 toggles it).
+    show_emitter_gizmos: bool,
+    // Color of the ambient light, which lights every surface evenly so the sides of particles
+    // facing away from the light aren't left black.
+    ambient_color: Color,
+    // Brightness of the ambient light (F5 / F6 lower / raise it).  It lights particles' own
+    // color, not their glow, so with emissive particles and bloom keep it low or the glow won't
+    // stand out against the rest of the scene.
+    ambient_brightness: f32,
+    // Whether there's distance fog, which fades far away things (particles that have flown off
+    // included) into fog_color.
+    show_fog: bool,
+    // Color of the fog.  It matches the default clear color, so the fog blends into the sky.
+    fog_color: Color,
+    // Distance from the camera the fog starts at...
+    fog_start: f32,
+    // ...and that it's fully thick at.
+    fog_end: f32,
+    // What lights the scene (only read at startup).
+    lighting: Lighting,
+    // Seconds of simulation time for a whole day, with sun lighting (0 = the sun stands still).
+    // Pausing the app pauses the cycle too.
+    day_length: f32,
+    // Whether particles cast shadows.  Every caster is drawn again into each of the point
+    // light's six shadow map faces, so with thousands of particles this can cost more than
+    // drawing them.  They receive shadows either way.
+    particle_shadows: bool,
+    // Only particles within this distance of the camera cast shadows (None = all of them), to
+    // keep the cost down while still showing depth where it's noticed.
+    shadow_caster_radius: Option<f32>,
+    // Height below which particles are retired straight away rather than left to fall until
+    // they expire (e.g. after being launched off the edge of the ground).
+    kill_plane_y: f32,
+    // Speed (into the ground) above which a particle hitting the ground splashes into smaller
+    // droplets.
+    splash_threshold_speed: f32,
+    // Number of droplets in each splash (0 switches splashing off).
+    splash_count: usize,
+    // Chance (0 to 1) that a particle spawned by an emitter comes with a second one joined to
+    // it, making a dumbbell.
+    pair_probability: f32,
+    // How the two halves of a dumbbell are joined.
+    pair_joint: PairJoint,
+    // Seconds a particle's rigid body may sleep (i.e. sit at rest) before the particle expires,
+    // or None to leave resting particles until they expire as usual.  With a death effect, the
+    // effect starts then, rather than the particle vanishing.
+    sleep_grace_period: Option<f32>,
+    // Number of bounces off the ground a particle survives; it expires on the next one (None =
+    // any number).  Hitting other particles doesn't count.
+    max_bounces: Option<u32>,
+    // Whether a brief flash pops up wherever a particle is retired.  Each flash is an entity of
+    // its own (though without physics), so it's off by default.
+    show_flashes: bool,
+    // Whether particles leave fading trails behind them ('U' toggles it).  Recording and drawing
+    // the trails costs time per particle per frame, so it's off by default.
+    show_trails: bool,
+    // Number of recent positions each trail keeps (capped at trails::MAX_TRAIL_LENGTH).
+    trail_length: usize,
+    // Whether particles are retired as soon as they hit the ground, as well as when they expire
+    // ('H' toggles it).
+    despawn_on_ground_hit: bool,
+    // Color of particles on the ground when coloring by height.
+    low_height_color: Color,
+    // Color of particles at HEIGHT_COLOR_MAX (and above) when coloring by height.
+    high_height_color: Color,
+    // Color of newly spawned particles when coloring by age.
+    start_color: Color,
+    // Color of particles about to expire when coloring by age.
+    end_color: Color,
+    // Color the shared particle material glows with.  Randomly colored particles glow in their
+    // own hue, and recolored ones (by speed, height or age) in their current color.
+    emissive_color: Color,
+    // How brightly particles glow: the glow is the emissive color times this (0 = no glow).
+    // Values above 1 only make a difference with HDR, and only bloom with --bloom.
+    emissive_strength: f32,
+    // The axis new particles are emitted around.
+    emission_axis: Vec3,
+    // Angle (in radians) the emission axis is tilted away from straight up, between 0 and PI / 2
+    // so it never points below the ground (Ctrl + up / down arrows steer it).
+    nozzle_tilt: f32,
+    // Direction (in radians, around the Y axis from +Z towards +X) the emission axis is tilted
+    // in (Ctrl + left / right arrows steer it).
+    nozzle_heading: f32,
+    // Full apex angle (in radians) of the emission cone.  0 is a perfectly straight
+    // jet along the emission axis, PI covers the whole hemisphere around it.
+    spread_angle: f32,
+    // Distribution of launch directions around the emission axis ('\' cycles through them).
+    emission_shape: EmissionShape,
+    // Whether launch directions are random or follow a spiral ('P' toggles it).
+    emission_pattern: EmissionPattern,
+    // Whether the emitters make a fountain or rain ('N' toggles it).
+    spawn_preset: SpawnPreset,
+    // Radians the spiral pattern turns between successive particles.
+    spiral_step: f32,
+    // Radians per second the spiral pattern turns on its own, between particles.
+    spiral_speed: f32,
+    // Whether the spawn rate backs off while the frame rate is below 'target_fps' (and recovers
+    // once it's above) ('L' toggles it).
+    adaptive_rate: bool,
+    // Frame rate the adaptive spawn rate aims to hold.
+    target_fps: f32,
+    // Range (min, max) of the adaptive spawn rate, as a fraction of the normal rate.  The
+    // minimum keeps emission from stopping altogether.
+    adaptive_rate_range: (f32, f32),
+    // Upper bound on the number of live particles (defaults to MAX_PARTICLES).  What happens
+    // when spawning another batch would exceed it is up to cap_policy.
+    max_particles: usize,
+    // Whether reaching max_particles holds spawning back or evicts the oldest particles.
+    cap_policy: CapPolicy,
+    // Number of particles spawned at once when a burst is triggered.
+    burst_size: usize,
+    // Max number of expired particles kept in the pool for reuse.  Anything that expires while
+    // the pool is full is despawned for real.
+    pool_size: usize,
+    // Whether setup adds the demo emitter that launches particles off the surface of a sphere
+    // (read at startup).
+    sphere_emitter: bool,
+    // Whether setup adds the demo emitter that launches particles off a ring around the middle of
+    // the ground (read at startup).
+    ring_emitter: bool,
+    // Whether setup adds the demo emitter that circles the middle of the scene (read at startup).
+    orbit_emitter: bool,
+    // Whether setup adds the curtain of stream emitters along the back edge of the ground (read
+    // at startup).
+    emitter_curtain: bool,
+    // Whether setup adds the demo torus hanging over the middle of the scene, with particles pouring
+    // off its surface (read at startup)
+    torus_emitter: bool,
+    // Number of emitters in the curtain of streams along the back edge of the ground (set up
+    // once at startup).
+    curtain_emitter_count: usize,
+    // Image (in the assets folder) to cover the ground with, or None for a flat color (read at
+    // startup).  If it fails to load, the ground falls back to the flat color.
+    ground_texture: Option<String>,
+    // Number of times the ground texture repeats along each side of the ground (read at startup).
+    ground_uv_tiling: f32,
+}
+
+impl Configuration {
+    // Sets the usual particle lifetime, scaling lifetime_range along with it (so (8, 12) around
+    // 10 seconds becomes (16, 24) around 20).
+    fn set_particle_expire_time(&mut self, expire_time: f32) {
+        self.lifetime_range = if self.particle_expire_time > 0.0 {
+            let factor = expire_time / self.particle_expire_time;
+            let (min, max) = self.lifetime_range;
+            (min * factor, max * factor)
+        } else {
+            (expire_time, expire_time)
+        };
+        self.particle_expire_time = expire_time;
+    }
+
+    // Creates the configuration with the built-in defaults, along with the meshes and materials
+    // it holds on to.
+    fn new(meshes: &mut Assets<Mesh>, materials: &mut Assets<StandardMaterial>) -> Self {
+        // Create the material the particles will use (kept here for later use)
+        let particle_material = materials.add(StandardMaterial {
+            base_color: Color::hex("#ff6060").unwrap(),
+            metallic: 1.0,
+            perceptual_roughness: 0.5,
+            ..default()
+        });
+
+        // Create the palette of materials used for randomly colored particles.
+        let palette_materials = (0..PALETTE_SIZE)
+            .map(|i| {
+                materials.add(StandardMaterial {
+                    base_color: Color::hsl(i as f32 * 360.0 / PALETTE_SIZE as f32, 0.8, 0.6),
+                    metallic: 1.0,
+                    perceptual_roughness: 0.5,
+                    ..default()
+                })
+            })
+            .collect();
+
+        // Create the flash mesh, and the materials a flash fades through.  Flashes are unlit so
+        // they look the same however the scene is lit.
+        let flash_mesh = meshes.add(
+            Mesh::try_from(shape::Icosphere {
+                radius: PARTICLE_RADIUS,
+                subdivisions: 2,
+            })
+            .unwrap(),
+        );
+        let flash_materials = (0..FLASH_FADE_STEPS)
+            .map(|i| {
+                let alpha = 1.0 - i as f32 / FLASH_FADE_STEPS as f32;
+                materials.add(StandardMaterial {
+                    base_color: FLASH_COLOR.with_a(alpha),
+                    emissive: FLASH_COLOR * alpha,
+                    unlit: true,
+                    alpha_mode: AlphaMode::Blend,
+                    ..default()
+                })
+            })
+            .collect();
+
+        // Create the mesh the particles will use (also kept here for later use)
+        let sphere_mesh = meshes.add(
+            Mesh::try_from(shape::Icosphere {
+                radius: PARTICLE_RADIUS,
+                ..default()
+            })
+            .unwrap(),
+        );
+
+        // Create the alternative cube mesh, with the same "radius" as the sphere.
+        let cube_mesh = meshes.add(Mesh::from(shape::Cube {
+            size: PARTICLE_RADIUS * 2.0,
+        }));
+
+        Configuration {
+            sphere_mesh,
+            cube_mesh,
+            particle_shape: ParticleShape::Sphere,
+            max_angular_speed: MAX_ANGULAR_SPEED,
+            particle_material,
+            palette_materials,
+            flash_mesh,
+            flash_materials,
+            particle_radius: PARTICLE_RADIUS,
+            particle_radius_range: (MIN_PARTICLE_RADIUS, MAX_PARTICLE_RADIUS),
+            spawn_delta: Duration::from_millis(PARTICLE_RESPAWN_TIME_MS),
+            spawn_count: SPAWN_COUNT,
+            particle_expire_time: PARTICLE_EXPIRE_TIME_SECS,
+            lifetime_range: LIFETIME_RANGE,
+            max_spawn_offset: MAX_SPAWN_OFFSET,
+            spawn_height: SPAWN_HEIGHT,
+            speed_distribution: SpeedDistribution::Constant(INITIAL_VELOCITY),
+            emitting: true,
+            emission_mode: EmissionMode::Continuous,
+            max_catch_up_batches: MAX_CATCH_UP_BATCHES,
+            stagger_batches: true,
+            spawn_rate: SPAWN_RATE,
+            pulse_amplitude: PULSE_AMPLITUDE,
+            pulse_period: PULSE_PERIOD_SECS,
+            coloring: ParticleColoring::Uniform,
+            death_effect: DeathEffect::None,
+            fade_out_time: FADE_OUT_SECS,
+            shrink_time: SHRINK_SECS,
+            restitution: RESTITUTION,
+            friction: FRICTION,
+            particle_collisions: true,
+            show_emitter_gizmos: false,
+            particle_shadows: true,
+            shadow_caster_radius: None,
+            ambient_color: Color::WHITE,
+            ambient_brightness: AMBIENT_BRIGHTNESS,
+            show_fog: true,
+            fog_color: Color::rgb(0.4, 0.4, 0.4),
+            fog_start: FOG_START,
+            fog_end: FOG_END,
+            lighting: Lighting::Point,
+            day_length: DAY_LENGTH_SECS,
+            kill_plane_y: KILL_PLANE_Y,
+            splash_threshold_speed: SPLASH_THRESHOLD_SPEED,
+            splash_count: SPLASH_COUNT,
+            pair_probability: PAIR_PROBABILITY,
+            pair_joint: PairJoint::Spherical,
+            sleep_grace_period: None,
+            max_bounces: None,
+            show_trails: false,
+            trail_length: TRAIL_LENGTH,
+            despawn_on_ground_hit: false,
+            low_height_color: Color::hex("#2040ff").unwrap(),
+            high_height_color: Color::hex("#ffe040").unwrap(),
+            start_color: Color::hex("#ffd020").unwrap(),
+            end_color: Color::hex("#c01000").unwrap(),
+            emissive_color: Color::hex("#ff6060").unwrap(),
+            emissive_strength: EMISSIVE_STRENGTH,
+            emission_axis: Vec3::Y,
+            nozzle_tilt: 0.0,
+            nozzle_heading: 0.0,
+            spread_angle: SPREAD_ANGLE,
+            emission_shape: EmissionShape::Cone,
+            emission_pattern: EmissionPattern::Random,
+            spawn_preset: SpawnPreset::Fountain,
+            spiral_step: SPIRAL_STEP,
+            spiral_speed: SPIRAL_SPEED,
+            adaptive_rate: false,
+            target_fps: TARGET_FPS,
+            adaptive_rate_range: ADAPTIVE_RATE_RANGE,
+            max_particles: MAX_PARTICLES,
+            cap_policy: CapPolicy::RefuseSpawn,
+            burst_size: BURST_SIZE,
+            pool_size: POOL_SIZE,
+            sphere_emitter: false,
+            ring_emitter: false,
+            orbit_emitter: false,
+            emitter_curtain: false,
+            torus_emitter: false,
+            curtain_emitter_count: CURTAIN_EMITTER_COUNT,
+            ground_texture: Some(GROUND_TEXTURE.to_string()),
+            ground_uv_tiling: GROUND_UV_TILING,
+            show_flashes: false,
+        }
+    }
+
+    // Whether 'count' more particles may be spawned with 'live_particles' already alive.  They
+    // always may when the oldest particles are evicted to make room.
+    fn fits_under_cap(&self, live_particles: usize, count: usize) -> bool {
+        self.cap_policy == CapPolicy::EvictOldest || live_particles + count <= self.max_particles
+    }
+
+    // How many of 'count' particles may be spawned with 'live_particles' already alive.
+    fn room_under_cap(&self, live_particles: usize, count: usize) -> usize {
+        match self.cap_policy {
+            CapPolicy::RefuseSpawn => count.min(self.max_particles.saturating_sub(live_particles)),
+            CapPolicy::EvictOldest => count,
+        }
+    }
+}
+
+// ConfigFile - the tunable (scalar) settings that can be read from CONFIG_FILE.  Settings left
+// out of the file keep their defaults.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+struct ConfigFile {
+    particle_radius_range: (f32, f32),
+    spawn_delta_ms: u64,
+    spawn_count: usize,
+    particle_expire_time: f32,
+    lifetime_range: (f32, f32),
+    fade_out_time: f32,
+    shrink_time: f32,
+    emissive_strength: f32,
+    max_spawn_offset: f32,
+    spawn_height: f32,
+    speed_distribution: SpeedDistribution,
+    max_angular_speed: f32,
+    spawn_rate: f32,
+    max_catch_up_batches: usize,
+    pulse_amplitude: f32,
+    pulse_period: f32,
+    spread_angle: f32,
+    spiral_step: f32,
+    spiral_speed: f32,
+    restitution: f32,
+    friction: f32,
+    particle_collisions: bool,
+    target_fps: f32,
+    adaptive_rate_range: (f32, f32),
+    max_particles: usize,
+    cap_policy: CapPolicy,
+    burst_size: usize,
+    pool_size: usize,
+    sphere_emitter: bool,
+    ring_emitter: bool,
+    orbit_emitter: bool,
+    emitter_curtain: bool,
+    torus_emitter: bool,
+    curtain_emitter_count: usize,
+    ground_texture: Option<String>,
+    ground_uv_tiling: f32,
+    show_flashes: bool,
+    trail_length: usize,
+    kill_plane_y: f32,
+    splash_threshold_speed: f32,
+    splash_count: usize,
+    pair_probability: f32,
+    pair_joint: PairJoint,
+    sleep_grace_period: Option<f32>,
+    max_bounces: Option<u32>,
+    particle_shadows: bool,
+    shadow_caster_radius: Option<f32>,
+    ambient_brightness: f32,
+    show_fog: bool,
+    fog_start: f32,
+    fog_end: f32,
+    lighting: Lighting,
+    day_length: f32,
+}
+
+impl Default for ConfigFile {
+    fn default() -> Self {
+        ConfigFile {
+            particle_radius_range: (MIN_PARTICLE_RADIUS, MAX_PARTICLE_RADIUS),
+            spawn_delta_ms: PARTICLE_RESPAWN_TIME_MS,
+            spawn_count: SPAWN_COUNT,
+            particle_expire_time: PARTICLE_EXPIRE_TIME_SECS,
+            lifetime_range: LIFETIME_RANGE,
+            fade_out_time: FADE_OUT_SECS,
+            shrink_time: SHRINK_SECS,
+            emissive_strength: EMISSIVE_STRENGTH,
+            max_spawn_offset: MAX_SPAWN_OFFSET,
+            spawn_height: SPAWN_HEIGHT,
+            speed_distribution: SpeedDistribution::Constant(INITIAL_VELOCITY),
+            max_angular_speed: MAX_ANGULAR_SPEED,
+            spawn_rate: SPAWN_RATE,
+            max_catch_up_batches: MAX_CATCH_UP_BATCHES,
+            pulse_amplitude: PULSE_AMPLITUDE,
+            pulse_period: PULSE_PERIOD_SECS,
+            spread_angle: SPREAD_ANGLE,
+            spiral_step: SPIRAL_STEP,
+            spiral_speed: SPIRAL_SPEED,
+            restitution: RESTITUTION,
+            friction: FRICTION,
+            particle_collisions: true,
+            target_fps: TARGET_FPS,
+            adaptive_rate_range: ADAPTIVE_RATE_RANGE,
+            max_particles: MAX_PARTICLES,
+            cap_policy: CapPolicy::RefuseSpawn,
+            burst_size: BURST_SIZE,
+            pool_size: POOL_SIZE,
+            sphere_emitter: false,
+            ring_emitter: false,
+            orbit_emitter: false,
+            emitter_curtain: false,
+            torus_emitter: false,
+            curtain_emitter_count: CURTAIN_EMITTER_COUNT,
+            ground_texture: Some(GROUND_TEXTURE.to_string()),
+            ground_uv_tiling: GROUND_UV_TILING,
+            show_flashes: false,
+            trail_length: TRAIL_LENGTH,
+            kill_plane_y: KILL_PLANE_Y,
+            splash_threshold_speed: SPLASH_THRESHOLD_SPEED,
+            splash_count: SPLASH_COUNT,
+            pair_probability: PAIR_PROBABILITY,
+            pair_joint: PairJoint::Spherical,
+            sleep_grace_period: None,
+            max_bounces: None,
+            particle_shadows: true,
+            shadow_caster_radius: None,
+            ambient_brightness: AMBIENT_BRIGHTNESS,
+            show_fog: true,
+            fog_start: FOG_START,
+            fog_end: FOG_END,
+            lighting: Lighting::Point,
+            day_length: DAY_LENGTH_SECS,
+        }
+    }
+}
+
+impl ConfigFile {
+    // Collects the tunable settings from the configuration.
+    fn from_configuration(configuration: &Configuration) -> Self {
+        ConfigFile {
+            particle_radius_range: configuration.particle_radius_range,
+            spawn_delta_ms: configuration.spawn_delta.as_millis() as u64,
+            spawn_count: configuration.spawn_count,
+            particle_expire_time: configuration.particle_expire_time,
+            lifetime_range: configuration.lifetime_range,
+            fade_out_time: configuration.fade_out_time,
+            shrink_time: configuration.shrink_time,
+            emissive_strength: configuration.emissive_strength,
+            max_spawn_offset: configuration.max_spawn_offset,
+            spawn_height: configuration.spawn_height,
+            speed_distribution: configuration.speed_distribution,
+            max_angular_speed: configuration.max_angular_speed,
+            spawn_rate: configuration.spawn_rate,
+            max_catch_up_batches: configuration.max_catch_up_batches,
+            pulse_amplitude: configuration.pulse_amplitude,
+            pulse_period: configuration.pulse_period,
+            spread_angle: configuration.spread_angle,
+            spiral_step: configuration.spiral_step,
+            spiral_speed: configuration.spiral_speed,
+            restitution: configuration.restitution,
+            friction: configuration.friction,
+            particle_collisions: configuration.particle_collisions,
+            target_fps: configuration.target_fps,
+            adaptive_rate_range: configuration.adaptive_rate_range,
+            max_particles: configuration.max_particles,
+            cap_policy: configuration.cap_policy,
+            burst_size: configuration.burst_size,
+            pool_size: configuration.pool_size,
+            sphere_emitter: configuration.sphere_emitter,
+            ring_emitter: configuration.ring_emitter,
+            orbit_emitter: configuration.orbit_emitter,
+            emitter_curtain: configuration.emitter_curtain,
+            torus_emitter: configuration.torus_emitter,
+            curtain_emitter_count: configuration.curtain_emitter_count,
+            ground_texture: configuration.ground_texture.clone(),
+            ground_uv_tiling: configuration.ground_uv_tiling,
+            show_flashes: configuration.show_flashes,
+            trail_length: configuration.trail_length,
+            kill_plane_y: configuration.kill_plane_y,
+            splash_threshold_speed: configuration.splash_threshold_speed,
+            splash_count: configuration.splash_count,
+            pair_probability: configuration.pair_probability,
+            pair_joint: configuration.pair_joint,
+            sleep_grace_period: configuration.sleep_grace_period,
+            max_bounces: configuration.max_bounces,
+            particle_shadows: configuration.particle_shadows,
+            shadow_caster_radius: configuration.shadow_caster_radius,
+            ambient_brightness: configuration.ambient_brightness,
+            show_fog: configuration.show_fog,
+            fog_start: configuration.fog_start,
+            fog_end: configuration.fog_end,
+            lighting: configuration.lighting,
+            day_length: configuration.day_length,
+        }
+    }
+
+    // Copies the settings into the configuration.
+    fn apply(&self, configuration: &mut Configuration) {
+        configuration.particle_radius_range = self.particle_radius_range;
+        configuration.spawn_delta = Duration::from_millis(self.spawn_delta_ms);
+        configuration.spawn_count = self.spawn_count;
+        configuration.particle_expire_time = self.particle_expire_time;
+        configuration.lifetime_range = self.lifetime_range;
+        configuration.fade_out_time = self.fade_out_time;
+        configuration.shrink_time = self.shrink_time;
+        configuration.emissive_strength = self.emissive_strength;
+        configuration.max_spawn_offset = self.max_spawn_offset;
+        configuration.spawn_height = self.spawn_height;
+        configuration.speed_distribution = self.speed_distribution;
+        configuration.max_angular_speed = self.max_angular_speed;
+        configuration.spawn_rate = self.spawn_rate;
+        configuration.max_catch_up_batches = self.max_catch_up_batches;
+        configuration.pulse_amplitude = self.pulse_amplitude;
+        configuration.pulse_period = self.pulse_period;
+        configuration.spread_angle = self.spread_angle;
+        configuration.spiral_step = self.spiral_step;
+        configuration.spiral_speed = self.spiral_speed;
+        configuration.restitution = self.restitution;
+        configuration.friction = self.friction;
+        configuration.particle_collisions = self.particle_collisions;
+        configuration.target_fps = self.target_fps;
+        configuration.adaptive_rate_range = self.adaptive_rate_range;
+        configuration.max_particles = self.max_particles;
+        configuration.cap_policy = self.cap_policy;
+        configuration.burst_size = self.burst_size;
+        configuration.pool_size = self.pool_size;
+        configuration.sphere_emitter = self.sphere_emitter;
+        configuration.ring_emitter = self.ring_emitter;
+        configuration.orbit_emitter = self.orbit_emitter;
+        configuration.emitter_curtain = self.emitter_curtain;
+        configuration.torus_emitter = self.torus_emitter;
+        configuration.curtain_emitter_count = self.curtain_emitter_count;
+        configuration.ground_texture = self.ground_texture.clone();
+        configuration.ground_uv_tiling = self.ground_uv_tiling;
+        configuration.show_flashes = self.show_flashes;
+        configuration.trail_length = self.trail_length;
+        configuration.kill_plane_y = self.kill_plane_y;
+        configuration.splash_threshold_speed = self.splash_threshold_speed;
+        configuration.splash_count = self.splash_count;
+        configuration.pair_probability = self.pair_probability;
+        configuration.pair_joint = self.pair_joint;
+        configuration.sleep_grace_period = self.sleep_grace_period;
+        configuration.max_bounces = self.max_bounces;
+        configuration.particle_shadows = self.particle_shadows;
+        configuration.shadow_caster_radius = self.shadow_caster_radius;
+        configuration.ambient_brightness = self.ambient_brightness;
+        configuration.show_fog = self.show_fog;
+        configuration.fog_start = self.fog_start;
+        configuration.fog_end = self.fog_end;
+        configuration.lighting = self.lighting;
+        configuration.day_length = self.day_length;
+    }
+}
+
+// load_config_file - reads the settings from CONFIG_FILE, falling back to the defaults if it's
+// missing or can't be parsed.  Which of those happened is logged.
+fn load_config_file() -> ConfigFile {
+    let text = match std::fs::read_to_string(CONFIG_FILE) {
+        Ok(text) => text,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+            info!("No {} found, using the default settings", CONFIG_FILE);
+            return ConfigFile::default();
+        }
+        Err(error) => {
+            warn!(
+                "Couldn't read {}: {}, using the default settings",
+                CONFIG_FILE, error
+            );
+            return ConfigFile::default();
+        }
+    };
+    match ron::from_str(&text) {
+        Ok(config_file) => {
+            info!("Loaded settings from {}", CONFIG_FILE);
+            config_file
+        }
+        Err(error) => {
+            warn!(
+                "Couldn't parse {}: {}, using the default settings",
+                CONFIG_FILE, error
+            );
+            ConfigFile::default()
+        }
+    }
+}
+
+// reload_config_file - an update system that checks CONFIG_FILE for changes every
+// CONFIG_POLL_SECS and, when it's been modified, applies its settings again (including to the
+// emitters and the ground).  A file that can't be read or parsed is reported and ignored, keeping
+// the settings from the last good one.  The file holds no mesh or material settings, so those
+// are never recreated.
+#[allow(clippy::too_many_arguments)]
+fn reload_config_file(
+    real_time: Res<Time<Real>>,
+    time: Res<Time>,
+    mut configuration: ResMut<Configuration>,
+    mut emitters: Query<&mut Emitter>,
+    mut ground: Query<&mut Friction, With<Ground>>,
+    mut watching: Local<bool>,
+    mut last_modified: Local<Option<std::time::SystemTime>>,
+    mut next_poll: Local<f32>,
+) {
+    let now = real_time.elapsed_seconds();
+    if now < *next_poll {
+        return;
+    }
+    *next_poll = now + CONFIG_POLL_SECS;
+
+    // The first poll just notes the file's state (setup has already loaded it), and a missing
+    // file leaves the settings alone.
+    let modified = std::fs::metadata(CONFIG_FILE)
+        .and_then(|metadata| metadata.modified())
+        .ok();
+    if !*watching {
+        *watching = true;
+        *last_modified = modified;
+        return;
+    }
+    if modified.is_none() || modified == *last_modified {
+        return;
+    }
+    *last_modified = modified;
+
+    let config_file = match std::fs::read_to_string(CONFIG_FILE)
+        .map_err(|error| error.to_string())
+        .and_then(|text| ron::from_str::<ConfigFile>(&text).map_err(|error| error.to_string()))
+    {
+        Ok(config_file) => config_file,
+        Err(error) => {
+            warn!(
+                "Couldn't reload {}: {}, keeping the current settings",
+                CONFIG_FILE, error
+            );
+            return;
+        }
+    };
+
+    let previous = configuration.clone();
+    config_file.apply(&mut configuration);
+    retune_emitters(
+        &previous,
+        &configuration,
+        &mut emitters,
+        time.elapsed_seconds(),
+    );
+    for mut friction in &mut ground {
+        friction.coefficient = configuration.friction;
+    }
+    info!("Reloaded settings from {}", CONFIG_FILE);
 }
 
+// SplashParticle - marks the droplets of a splash, so they don't splash themselves.
+#[derive(Component)]
+struct SplashParticle;
+
+// PreviousVelocity - a component holding a particle's velocity from before the latest physics
+// step.  By the time a collision is reported the contact has already slowed the particle down,
+// so this is what tells how hard it hit.
+#[derive(Component)]
+struct PreviousVelocity(Vec3);
+
+// Ground - this component marks the ground entity, so collisions with it can be recognized.
+#[derive(Component)]
+struct Ground;
+
 // Particle - A bundle (bevy-speak) containing the components that define a particle.
 #[derive(Bundle)]
 struct Particle {
+    // When was this particle spawned
+    spawn_time: SpawnTime,
     // When should this particle expire (despawn)
     expire_time: ExpireTime,
+    // How often it has bounced off the ground
+    bounce_count: BounceCount,
+    // Why it's going to be retired
+    retire_cause: RetireCause,
+    // The scale the particle was spawned with
+    scale: ParticleScale,
     // Marker denoting this entity is a particle
     marker: ParticleMarker,
     // Particle's velocity vector
     velocity: Velocity,
+    // ...and what it was before the latest physics step
+    previous_velocity: PreviousVelocity,
+    // External forces (e.g. wind) acting on the particle
+    external_force: ExternalForce,
+    // One-off impulses (e.g. explosions) to apply to the particle
+    external_impulse: ExternalImpulse,
+    // Report the particle's collisions (used to retire particles when they hit the ground)
+    active_events: ActiveEvents,
     // Particles geometry
     geometry: PbrBundle,
 }
@@ -84,153 +1906,3295 @@ fn setup(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
+    asset_server: Res<AssetServer>,
+    args: Res<Args>,
 ) {
-    // Create the material the particles will use (this will be added to the configuration
-    // resource for later use)
-    let particle_material = materials.add(StandardMaterial {
-        base_color: Color::hex("#ff6060").unwrap(),
+    // Create a second material so one of the emitters can be told apart from the rest.
+    let alternate_material = materials.add(StandardMaterial {
+        base_color: Color::hex("#60a0ff").unwrap(),
         metallic: 1.0,
         perceptual_roughness: 0.5,
         ..default()
     });
 
-    // Create the mesh the particles will use (this will be added to the configuration resource
-    // for later use)
-    let sphere_mesh = meshes.add(
-        Mesh::try_from(shape::Icosphere {
-            radius: PARTICLE_RADIUS,
-            ..default()
-        })
-        .unwrap(),
+    // Create the configuration resource (it's added to the world once the emitters are set up).
+    let mut configuration = Configuration::new(&mut meshes, &mut materials);
+
+    // Override the defaults with whatever the config file sets, and that with whatever the
+    // command line sets.  Log the result so a run can be reproduced.
+    load_config_file().apply(&mut configuration);
+    args.apply(&mut configuration);
+    info!(
+        "Configuration: {:?}",
+        ConfigFile::from_configuration(&configuration)
     );
 
-    // Add the configuration resource to the world.
-    commands.insert_resource(Configuration {
-        sphere_mesh,
-        particle_material,
-        spawn_delta: Duration::from_millis(PARTICLE_RESPAWN_TIME_MS),
-    });
+    // Spawn a few emitters near the corners of the ground plane, each with its own timing
+    // and velocity so they're easy to tell apart.
+    commands.spawn((
+        TransformBundle::from(Transform::from_xyz(-6.0, 0.0, -6.0)),
+        Emitter::new(&configuration),
+    ));
+    commands.spawn((
+        TransformBundle::from(Transform::from_xyz(6.0, 0.0, -6.0)),
+        Emitter {
+            spawn_delta: configuration.spawn_delta * 2,
+            speed: configuration.speed_distribution.scaled(2.0),
+            material: Some(alternate_material),
+            ..Emitter::new(&configuration)
+        },
+    ));
+    commands.spawn(
+        EmitterBundle::from_configuration(&configuration)
+            .count(configuration.spawn_count / 2)
+            .lifetime(Duration::from_secs_f32(
+                configuration.particle_expire_time / 2.0,
+            ))
+            .at(Vec3::new(-6.0, 0.0, 6.0)),
+    );
 
-    // Create the ground entity
-    {
-        // Define the ground's boundary.  The will be given to the
-        // ground's collider so it interacts with the physics engine)
-        let ground_boundary = &[
-            Vec3::new(GROUND_RADIUS, 0., GROUND_RADIUS),
-            Vec3::new(GROUND_RADIUS, 0., -GROUND_RADIUS),
-            Vec3::new(-GROUND_RADIUS, 0., -GROUND_RADIUS),
-            Vec3::new(-GROUND_RADIUS, 0., GROUND_RADIUS),
-            Vec3::new(GROUND_RADIUS, -10.0, GROUND_RADIUS),
+    // The demo emitters below are only added when switched on in the config file.
+    if configuration.sphere_emitter {
+        commands.spawn(
+            EmitterBundle::from_configuration(&configuration)
+                .shape(EmitterShape::Sphere { radius: 1.0 })
+                .at(Vec3::new(6.0, 6.0, 6.0)),
+        );
+    }
+    if configuration.ring_emitter {
+        commands.spawn(
+            EmitterBundle::from_configuration(&configuration)
+                .shape(EmitterShape::Ring { radius: 2.0 })
+                .at(Vec3::new(0.0, 1.0, 0.0)),
+        );
+    }
+
+    // ...and one that circles the middle of the scene, leaving a spiral on the ground.
+    if configuration.orbit_emitter {
+        commands.spawn((
+            TransformBundle::from(Transform::from_xyz(ORBIT_RADIUS, 0.0, 0.0)),
+            Emitter {
+                inherit_factor: 1.0,
+                ..Emitter::new(&configuration)
+            },
+            Orbit {
+                center: Vec3::ZERO,
+                radius: ORBIT_RADIUS,
+                angular_speed: ORBIT_ANGULAR_SPEED,
+            },
+        ));
+    }
+
+    // ...and a curtain of thin streams along the back edge.
+    if configuration.emitter_curtain {
+        spawn_emitter_line(
+            &mut commands,
+            &configuration,
+            Vec3::new(-GROUND_RADIUS + 1.0, 0.0, -GROUND_RADIUS + 1.0),
+            Vec3::new(GROUND_RADIUS - 1.0, 0.0, -GROUND_RADIUS + 1.0),
+            configuration.curtain_emitter_count,
+        );
+    }
+
+    // ...and a torus hanging over the middle of the scene, with particles pouring off its surface.
+    if configuration.torus_emitter {
+        let torus = Mesh::from(shape::Torus {
+            radius: 2.0,
+            ring_radius: 0.5,
+            subdivisions_segments: 24,
+            subdivisions_sides: 12,
+        });
+        commands.insert_resource(SurfaceSampler::from_mesh(&torus));
+        let collider = Collider::from_bevy_mesh(&torus, &ComputedColliderShape::TriMesh).unwrap();
+        commands
+            .spawn(PbrBundle {
+                mesh: meshes.add(torus),
+                material: materials.add(Color::rgb(0.6, 0.6, 0.7).into()),
+                transform: Transform::from_translation(TORUS_POSITION),
+                ..Default::default()
+            })
+            .insert(RigidBody::Fixed)
+            .insert(collider);
+        commands.spawn(
+            EmitterBundle::from_configuration(&configuration)
+                .shape(EmitterShape::Surface)
+                .speed(configuration.speed_distribution.mean() * TORUS_SPEED_FACTOR)
+                .at(TORUS_POSITION),
+        );
+    }
+
+    // Run the demo emission script, which goes through the emission phases in a loop.
+    commands.insert_resource(EmissionScript::demo(&configuration));
+
+    // Add the configuration resource to the world.
+    let ground_friction = configuration.friction;
+    let lighting = configuration.lighting;
+    // (there's nothing to show a texture on without a window)
+    let ground_texture = configuration
+        .ground_texture
+        .clone()
+        .filter(|_| !args.headless);
+    let ground_uv_tiling = configuration.ground_uv_tiling;
+    let fog = configuration.show_fog.then(|| fog_settings(&configuration));
+    commands.insert_resource(AmbientLight {
+        color: configuration.ambient_color,
+        brightness: configuration.ambient_brightness,
+    });
+    commands.insert_resource(configuration);
+
+    // Create the ground entity
+    {
+        // Define the ground's boundary.  The will be given to the
+        // ground's collider so it interacts with the physics engine)
+        let ground_boundary = &[
+            Vec3::new(GROUND_RADIUS, 0., GROUND_RADIUS),
+            Vec3::new(GROUND_RADIUS, 0., -GROUND_RADIUS),
+            Vec3::new(-GROUND_RADIUS, 0., -GROUND_RADIUS),
+            Vec3::new(-GROUND_RADIUS, 0., GROUND_RADIUS),
+            Vec3::new(GROUND_RADIUS, -10.0, GROUND_RADIUS),
             Vec3::new(GROUND_RADIUS, -10.0, -GROUND_RADIUS),
             Vec3::new(-GROUND_RADIUS, -10.0, -GROUND_RADIUS),
             Vec3::new(-GROUND_RADIUS, -10.0, GROUND_RADIUS),
         ];
 
+        // Stretch the plane's UVs so the texture repeats across it (the sampler wraps them
+        // back around).
+        let mut ground_mesh = Mesh::from(shape::Plane {
+            size: GROUND_RADIUS * 2.0,
+            subdivisions: 16,
+        });
+        if let Some(VertexAttributeValues::Float32x2(uvs)) =
+            ground_mesh.attribute_mut(Mesh::ATTRIBUTE_UV_0)
+        {
+            for uv in uvs.iter_mut() {
+                uv[0] *= ground_uv_tiling;
+                uv[1] *= ground_uv_tiling;
+            }
+        }
+
+        // Texture the ground if asked to (fall_back_to_flat_ground covers for a missing
+        // texture), otherwise give it a flat color.
+        let ground_material = match ground_texture {
+            Some(path) => StandardMaterial {
+                base_color_texture: Some(asset_server.load_with_settings(
+                    path,
+                    |settings: &mut ImageLoaderSettings| {
+                        settings.sampler = ImageSampler::Descriptor(ImageSamplerDescriptor {
+                            address_mode_u: ImageAddressMode::Repeat,
+                            address_mode_v: ImageAddressMode::Repeat,
+                            ..default()
+                        });
+                    },
+                )),
+                ..default()
+            },
+            None => GROUND_COLOR.into(),
+        };
+
         // Spawn the ground plane - then insert the physics type and collider.
         commands
             .spawn(PbrBundle {
-                mesh: meshes.add(Mesh::from(shape::Plane {
-                    size: GROUND_RADIUS * 2.0,
-                    subdivisions: 16,
-                })),
-                material: materials.add(Color::rgb(0.4, 0.4, 0.4).into()),
-                transform: Transform::from_translation(Vec3::Y / 2.0),
+                mesh: meshes.add(ground_mesh),
+                material: materials.add(ground_material),
+                transform: Transform::from_translation(Vec3::Y * GROUND_HEIGHT),
                 ..Default::default()
             })
             .insert(RigidBody::Fixed)
-            .insert(Collider::convex_hull(ground_boundary).unwrap());
+            .insert(Collider::convex_hull(ground_boundary).unwrap())
+            .insert(Friction {
+                coefficient: ground_friction,
+                combine_rule: CoefficientCombineRule::Average,
+            })
+            .insert(Ground);
     }
 
-    // // Spawn a simple point light
-    commands.spawn(PointLightBundle {
-        transform: Transform::from_xyz(50.0, 50.0, 0.0),
-        point_light: PointLight {
-            intensity: 600000.,
-            range: 500.,
-            shadows_enabled: true,
-            ..default()
-        },
-        ..default()
-    });
+    // Spawn a simple point light, or the sun (which move_sun then moves across the sky)
+    match lighting {
+        Lighting::Point => {
+            commands.spawn(PointLightBundle {
+                transform: Transform::from_xyz(50.0, 50.0, 0.0),
+                point_light: PointLight {
+                    intensity: 600000.,
+                    range: 500.,
+                    shadows_enabled: true,
+                    ..default()
+                },
+                ..default()
+            });
+        }
+        Lighting::Sun => {
+            commands.spawn((
+                DirectionalLightBundle {
+                    directional_light: DirectionalLight {
+                        illuminance: SUN_ILLUMINANCE,
+                        shadows_enabled: true,
+                        ..default()
+                    },
+                    transform: Transform::from_xyz(0.0, 50.0, 0.0).looking_at(Vec3::ZERO, Vec3::Z),
+                    ..default()
+                },
+                Sun,
+            ));
+        }
+    }
 
-    // Spawn a simple perspective camera
-    commands.spawn((
+    // Spawn a simple perspective camera that can be orbited with the mouse
+    let orbit = OrbitCamera::looking_at(Vec3::new(20.0, 20.0, 20.0), Vec3::default());
+    let mut camera = commands.spawn((
         Camera3dBundle {
-            transform: Transform::from_xyz(20.0, 20.0, 20.0).looking_at(Vec3::default(), Vec3::Y),
+            camera: Camera {
+                // Bloom only has bright enough colors to work with in HDR.
+                hdr: args.bloom,
+                ..default()
+            },
+            transform: orbit.transform(),
             projection: PerspectiveProjection { ..default() }.into(),
             ..default()
         },
         Fxaa::default(),
+        orbit,
     ));
+    if args.bloom {
+        camera.insert(BloomSettings::NATURAL);
+    }
+    if let Some(fog) = fog {
+        camera.insert(fog);
+    }
+}
+
+// fall_back_to_flat_ground - an update system that gives the ground its flat color instead of
+// its texture if the texture failed to load, since the ground wouldn't be drawn at all otherwise.
+fn fall_back_to_flat_ground(
+    asset_server: Res<AssetServer>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    ground: Query<&Handle<StandardMaterial>, With<Ground>>,
+) {
+    for handle in ground.iter() {
+        let Some(texture) = materials
+            .get(handle)
+            .and_then(|material| material.base_color_texture.as_ref())
+        else {
+            continue;
+        };
+        if asset_server.get_load_state(texture.id()) != Some(LoadState::Failed) {
+            continue;
+        }
+
+        warn!("Ground texture failed to load, using a flat color instead");
+        if let Some(material) = materials.get_mut(handle) {
+            material.base_color_texture = None;
+            material.base_color = GROUND_COLOR;
+        }
+    }
+}
+
+// spawn_emitter_line - spawns 'count' emitters evenly spaced from 'start' to 'end' (inclusive),
+// each emitting a thin stream.  Between them they spawn as many particles as a single regular
+// emitter would.
+fn spawn_emitter_line(
+    commands: &mut Commands,
+    configuration: &Configuration,
+    start: Vec3,
+    end: Vec3,
+    count: usize,
+) {
+    for i in 0..count {
+        // A single emitter goes in the middle of the line.
+        let t = if count > 1 {
+            i as f32 / (count - 1) as f32
+        } else {
+            0.5
+        };
+        commands.spawn(
+            EmitterBundle::from_configuration(configuration)
+                .count((configuration.spawn_count / count).max(1))
+                .rate(configuration.spawn_rate / count as f32)
+                .shape(EmitterShape::Disc {
+                    radius: CURTAIN_STREAM_RADIUS,
+                })
+                .at(start.lerp(end, t)),
+        );
+    }
+}
+
+// orbit_emitters - an update system that moves every Orbit entity along its circle.  The
+// position is derived from the elapsed time so it's independent of the frame rate.
+fn orbit_emitters(time: Res<Time>, mut query: Query<(&mut Transform, &Orbit)>) {
+    for (mut transform, orbit) in query.iter_mut() {
+        let angle = time.elapsed_seconds() * orbit.angular_speed;
+        transform.translation =
+            orbit.center + Vec3::new(angle.cos(), 0.0, angle.sin()) * orbit.radius;
+    }
 }
 
-// spawn_particle - an 'update' system that spawns new particles if it's time to do so.
+// spawn_particle - an 'update' system that spawns new particles from every emitter that is due
+// to do so.
+#[allow(clippy::too_many_arguments)]
 fn spawn_particles(
     configuration: Res<Configuration>,
-    mut next_spawn_deadline: Local<ExpireTime>,
+    time: Res<Time>,
+    mut emitters: Query<(&Transform, &mut Emitter)>,
+    mut spawn_stats: ResMut<SpawnStats>,
+    particles: Query<(), With<ParticleMarker>>,
+    pool: Query<Entity, With<PooledParticle>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut last_cap_warning: Local<Option<f32>>,
+    mut rng: ResMut<ParticleRng>,
+    mut spawned: EventWriter<ParticleSpawned>,
+    diagnostics: Res<DiagnosticsStore>,
+    mut adaptive_rate: ResMut<AdaptiveRate>,
+    surface: Option<Res<SurfaceSampler>>,
     mut commands: Commands,
 ) {
-    // If it's time to spawn more particles...
-    if Instant::now() > next_spawn_deadline.0 {
-        // Spawn 'SPAWN_COUNT' particles
-        for _ in 0..SPAWN_COUNT {
-            // Create three random vector components that will be the initial velocity
-            // vector of the new particle
-            let x = ((random::<f32>() * 2.0) - 1.0) * 0.25;
-            let y = 1.0;
-            let z = ((random::<f32>() * 2.0) - 1.0) * 0.25;
-
-            // Create the initial velocity vector
-            let v = Vec3::new(x, y, z).normalize() * INITIAL_VELOCITY;
-
-            // Create a random vector that will contain the initial starting position
-            // of the particle.
-            let x = 1.0 + random::<f32>() * 2.0;
-            let y = MAX_SPAWN_OFFSET + 1.0 + random::<f32>() * 1.0;
-            let z = 1.0 + random::<f32>() * 2.0;
-
-            // Spawn the particle using our Particle bundle struct.
-            commands
-                .spawn(Particle {
-                    expire_time: ExpireTime(
-                        Instant::now() + Duration::from_secs(PARTICLE_EXPIRE_TIME_SECS),
-                    ),
-                    marker: ParticleMarker {},
-                    velocity: Velocity {
-                        linvel: v,
-                        angvel: Vec3::ZERO,
-                    },
+    let rng = &mut rng.0;
+    let mut pool = pool.iter();
+    let now = time.elapsed_seconds();
+    let mut live_particles = particles.iter().count();
 
-                    // Set up the PBR bundle for the geometry that represents the particle (a simple sphere)
-                    geometry: PbrBundle {
-                        mesh: configuration.sphere_mesh.clone(),
-                        transform: Transform::from_translation(Vec3::new(x, y, z)),
-                        material: configuration.particle_material.clone(),
-                        ..default()
-                    },
-                })
-                // Insert a dynamic rigid body component for the particle
-                .insert(RigidBody::Dynamic)
-                // Insert a collider component for the particle
-                .insert(Collider::ball(PARTICLE_RADIUS));
+    // Nudge the adaptive rate up or down in proportion to how far the frame rate is from the
+    // target.  Without an FPS reading yet, leave it where it is.
+    let rate_scale = if configuration.adaptive_rate {
+        let fps = diagnostics
+            .get(FrameTimeDiagnosticsPlugin::FPS)
+            .and_then(|fps| fps.smoothed());
+        if let Some(fps) = fps {
+            let error = (fps as f32 - configuration.target_fps) / configuration.target_fps;
+            let (min_scale, max_scale) = configuration.adaptive_rate_range;
+            adaptive_rate.0 = (adaptive_rate.0 + error * ADAPTIVE_RATE_GAIN * time.delta_seconds())
+                .clamp(min_scale, max_scale);
+        }
+        adaptive_rate.0
+    } else {
+        1.0
+    };
+
+    for (emitter_transform, mut emitter) in emitters.iter_mut() {
+        // Track how fast the emitter itself is moving.
+        let position = emitter_transform.translation;
+        if let Some(last_position) = emitter.last_position {
+            if time.delta_seconds() > 0.0 {
+                emitter.velocity = (position - last_position) / time.delta_seconds();
+            }
+        }
+        emitter.last_position = Some(position);
+
+        // Keep the spiral turning even between spawns.
+        emitter.spiral_phase = (emitter.spiral_phase
+            + configuration.spiral_speed * time.delta_seconds())
+            % std::f32::consts::TAU;
+
+        // While emission is switched off, keep the emitter's schedule current so it doesn't
+        // try to catch up on the whole pause when it's switched back on.
+        if !configuration.emitting {
+            emitter.next_spawn = now;
+            emitter.accumulator = 0.0;
+            continue;
+        }
+
+        // Work out how many particles this emitter should spawn this frame, and how long a
+        // stretch of time they'd be spread over had they been emitted one by one.
+        let (count, interval) = match configuration.emission_mode {
+            EmissionMode::Batch => {
+                // Skip emitters that aren't due to spawn yet.
+                if now <= emitter.next_spawn {
+                    continue;
+                }
+
+                // A long frame may have skipped several intervals; catch up on them (up to a
+                // limit, so a slow frame can't snowball into slower ones) and keep the
+                // deadline on the original schedule.
+                let spawn_delta = emitter.spawn_delta.as_secs_f32();
+                let intervals = ((now - emitter.next_spawn) / spawn_delta).floor() + 1.0;
+                emitter.next_spawn += intervals * spawn_delta;
+                let batches = (intervals as usize).min(configuration.max_catch_up_batches);
+                let count = emitter.spawn_count * batches;
+                let count = ((count as f32 * rate_scale).round() as usize).max(1);
+                (count, spawn_delta * batches as f32)
+            }
+            EmissionMode::Continuous | EmissionMode::Pulsed => {
+                // Accumulate fractional particles and carry the remainder to the next frame.
+                let mut rate = emitter.ramp.map_or(emitter.rate, |ramp| ramp.rate_at(now));
+                rate *= rate_scale;
+                if configuration.emission_mode == EmissionMode::Pulsed {
+                    rate *= pulse_factor(
+                        configuration.pulse_amplitude,
+                        configuration.pulse_period,
+                        now,
+                    );
+                }
+                emitter.accumulator += rate * time.delta_seconds();
+                let count = emitter.accumulator.floor();
+                emitter.accumulator -= count;
+                (count as usize, 0.0)
+            }
+        };
+
+        // Only spawn if the whole batch fits under the cap (unless room is made by evicting).
+        // Spawning a partial batch would bias the spawn positions, so skip this frame and try
+        // again on the next one.
+        if count == 0 {
+            continue;
+        }
+        if !configuration.fits_under_cap(live_particles, count) {
+            // Say why emission stopped, but don't flood the log while the cap holds.
+            if last_cap_warning.map_or(true, |last| now - last >= CAP_WARNING_INTERVAL_SECS) {
+                warn!(
+                    "Particle cap of {} reached, skipping spawns",
+                    configuration.max_particles
+                );
+                *last_cap_warning = Some(now);
+            }
+            continue;
+        }
+
+        // Spawn 'count' particles
+        let mut speeds = Vec::with_capacity(count);
+        for i in 0..count {
+            let (position, linvel, expire_time) = match configuration.spawn_preset {
+                SpawnPreset::Fountain => {
+                    // Pick a starting position (relative to the emitter) and direction from the
+                    // emitter's shape, and scale the direction to create the initial velocity
+                    // vector.
+                    let (offset, mut direction) =
+                        sample_shape(emitter.shape, &configuration, surface.as_deref(), rng);
+                    if configuration.emission_pattern == EmissionPattern::Spiral
+                        && !matches!(
+                            emitter.shape,
+                            EmitterShape::Sphere { .. } | EmitterShape::Surface
+                        )
+                    {
+                        direction = spiral_direction(
+                            configuration.emission_axis,
+                            configuration.spread_angle,
+                            emitter.spiral_phase,
+                        );
+                        emitter.spiral_phase = (emitter.spiral_phase + configuration.spiral_step)
+                            % std::f32::consts::TAU;
+                    }
+                    let speed = emitter.speed.sample(rng);
+                    speeds.push(speed);
+                    let linvel = direction * speed + emitter.velocity * emitter.inherit_factor;
+                    (
+                        emitter_transform.translation + offset,
+                        linvel,
+                        sample_lifetime(&configuration, emitter.expire_time, rng),
+                    )
+                }
+                SpawnPreset::Rain => {
+                    let (position, linvel) = sample_rain(rng);
+                    speeds.push(linvel.length());
+                    let lifetime = sample_lifetime(&configuration, RAIN_EXPIRE_TIME_SECS, rng);
+                    (position, linvel, lifetime)
+                }
+            };
+
+            // Rather than launching the whole batch from the same spot at once, pretend each
+            // particle left at its own moment during the interval: move it along as far as it
+            // would have travelled since, and shorten its life to match.
+            let age = if configuration.stagger_batches {
+                (i as f32 + rng.gen::<f32>()) / count as f32 * interval
+            } else {
+                0.0
+            };
+
+            let position = position + linvel * age;
+            let spawn_time = now - age;
+            let expire_time = spawn_time + expire_time;
+            let first = spawn_particle(
+                &mut commands,
+                &configuration,
+                &mut materials,
+                &mut pool,
+                rng,
+                &mut spawned,
+                position,
+                linvel,
+                spawn_time,
+                expire_time,
+                emitter.mesh.as_ref(),
+                emitter.material.as_ref(),
+            );
+
+            // Now and then, make it a dumbbell: a second particle alongside, joined to the first.
+            // The cap check above made room for the whole batch, so the second half needs room
+            // on top of that; without it, the particle stays single.
+            if rng.gen::<f32>() < configuration.pair_probability
+                && configuration.fits_under_cap(live_particles + count, 1)
+            {
+                let offset = sample_sphere(rng) * PAIR_SPACING;
+                let second = spawn_particle(
+                    &mut commands,
+                    &configuration,
+                    &mut materials,
+                    &mut pool,
+                    rng,
+                    &mut spawned,
+                    position + offset,
+                    linvel,
+                    spawn_time,
+                    expire_time,
+                    emitter.mesh.as_ref(),
+                    emitter.material.as_ref(),
+                );
+                commands.entity(second).insert((
+                    ImpulseJoint::new(first, pair_joint(configuration.pair_joint, offset)),
+                    PairPartner(first),
+                ));
+                commands.entity(first).insert(PairPartner(second));
+                live_particles += 1;
+            }
         }
+        live_particles += count;
+        spawn_stats.record(&speeds);
+    }
+}
 
-        // Udpate the deadline for the next round of particle spawns.
-        *next_spawn_deadline = ExpireTime(Instant::now() + configuration.spawn_delta);
+// sample_rain - returns a random starting position (anywhere over the ground, at RAIN_HEIGHT)
+// and initial velocity (mostly straight down) for a rain drop.
+fn sample_rain(rng: &mut impl Rng) -> (Vec3, Vec3) {
+    let x = (rng.gen::<f32>() * 2.0 - 1.0) * GROUND_RADIUS;
+    let z = (rng.gen::<f32>() * 2.0 - 1.0) * GROUND_RADIUS;
+    let jitter = Vec2::new(rng.gen::<f32>() * 2.0 - 1.0, rng.gen::<f32>() * 2.0 - 1.0)
+        .clamp_length_max(1.0)
+        * RAIN_JITTER;
+    (
+        Vec3::new(x, RAIN_HEIGHT, z),
+        Vec3::new(jitter.x, -RAIN_SPEED, jitter.y),
+    )
+}
+
+// sample_lifetime - returns a random lifetime (in seconds) for a new particle whose usual
+// lifetime is 'lifetime', drawn from Configuration::lifetime_range.  The range is for the
+// configured particle_expire_time, so it's scaled for particles that usually live longer or
+// shorter.
+fn sample_lifetime(configuration: &Configuration, lifetime: f32, rng: &mut impl Rng) -> f32 {
+    if configuration.particle_expire_time <= 0.0 {
+        return lifetime;
     }
+    let (min, max) = configuration.lifetime_range;
+    let seconds = min + rng.gen::<f32>() * (max - min);
+    seconds * (lifetime / configuration.particle_expire_time)
 }
 
-// despawn_particles - an update system that will despawn any particles that have outlived
-// their expire-time.
-fn despawn_particles(
+// pair_joint - returns the joint for a dumbbell whose second half sits 'offset' away from the
+// first, anchored at the midpoint between them.
+fn pair_joint(kind: PairJoint, offset: Vec3) -> GenericJoint {
+    let anchor1 = offset * 0.5;
+    let anchor2 = -offset * 0.5;
+    match kind {
+        PairJoint::Spherical => SphericalJointBuilder::new()
+            .local_anchor1(anchor1)
+            .local_anchor2(anchor2)
+            .into(),
+        PairJoint::Fixed => FixedJointBuilder::new()
+            .local_anchor1(anchor1)
+            .local_anchor2(anchor2)
+            .into(),
+    }
+}
+
+// particle_collision_groups - returns the collision groups for a new particle.  Particles are
+// all in PARTICLE_GROUP, and take part in collisions with it unless particle collisions are off;
+// the ground and the rest of the scenery keep the default groups (everything), so particles
+// always collide with them.
+fn particle_collision_groups(configuration: &Configuration) -> CollisionGroups {
+    let filters = if configuration.particle_collisions {
+        Group::ALL
+    } else {
+        Group::ALL - PARTICLE_GROUP
+    };
+    CollisionGroups::new(PARTICLE_GROUP, filters)
+}
+
+// pulse_factor - returns what to multiply the spawn rate by at elapsed time 'now' in pulsed
+// mode: 1 + amplitude * sin(2 pi now / period).  A period or amplitude of zero gives a steady 1.
+fn pulse_factor(amplitude: f32, period: f32, now: f32) -> f32 {
+    if period <= 0.0 {
+        return 1.0;
+    }
+    let phase = now / period * std::f32::consts::TAU;
+    // The rate can't go negative, however large the amplitude.
+    (1.0 + amplitude * phase.sin()).max(0.0)
+}
+
+// spawn_particle - spawns a single particle at 'position' moving with 'linvel' that was born at
+// elapsed time 'spawn_time' and despawns once the elapsed time reaches 'expire_time'.  A pooled
+// particle is reused when available.  'mesh' and 'material' override the configuration's when
+// given.  A ParticleSpawned event is sent for the new particle, and its entity is returned.
+#[allow(clippy::too_many_arguments)]
+fn spawn_particle(
+    commands: &mut Commands,
+    configuration: &Configuration,
+    materials: &mut Assets<StandardMaterial>,
+    pool: &mut impl Iterator<Item = Entity>,
+    rng: &mut impl Rng,
+    spawned: &mut EventWriter<ParticleSpawned>,
+    position: Vec3,
+    linvel: Vec3,
+    spawn_time: f32,
+    expire_time: f32,
+    mesh: Option<&Handle<Mesh>>,
+    material: Option<&Handle<StandardMaterial>>,
+) -> Entity {
+    // Pick the particle's base material: the override, the shared one, or a random one from
+    // the palette.
+    let base_material = match (material, configuration.coloring) {
+        (Some(material), _) => material.clone(),
+        (None, ParticleColoring::RandomHue) => {
+            let index = rng.gen_range(0..configuration.palette_materials.len());
+            configuration.palette_materials[index].clone()
+        }
+        (None, _) => configuration.particle_material.clone(),
+    };
+
+    // Recoloring or fading needs a private copy of the material; otherwise share it.
+    let fading =
+        configuration.death_effect == DeathEffect::Fade && configuration.fade_out_time > 0.0;
+    let private_material = !matches!(
+        configuration.coloring,
+        ParticleColoring::Uniform | ParticleColoring::RandomHue
+    ) || fading;
+    let material = if private_material {
+        let mut material = materials.get(&base_material).cloned().unwrap_or_default();
+        if fading {
+            material.alpha_mode = AlphaMode::Blend;
+        }
+        materials.add(material)
+    } else {
+        base_material
+    };
+
+    // Pick a random radius.  Scaling the transform scales both the mesh and (through Rapier's
+    // transform sync) the collider, and Rapier derives the mass from the collider's volume so
+    // bigger particles are heavier.
+    let (min_radius, max_radius) = configuration.particle_radius_range;
+    let radius = min_radius + rng.gen::<f32>() * (max_radius - min_radius);
+    let scale = radius / configuration.particle_radius;
+    let transform = Transform::from_translation(position).with_scale(Vec3::splat(scale));
+
+    // Pick the geometry, and a random spin (in a uniformly random direction).
+    // The collider always follows the configured shape, even when the mesh is overridden.
+    let (default_mesh, collider) = match configuration.particle_shape {
+        ParticleShape::Sphere => (
+            configuration.sphere_mesh.clone(),
+            Collider::ball(configuration.particle_radius),
+        ),
+        ParticleShape::Cube => (
+            configuration.cube_mesh.clone(),
+            Collider::cuboid(
+                configuration.particle_radius,
+                configuration.particle_radius,
+                configuration.particle_radius,
+            ),
+        ),
+    };
+    let mesh = mesh.cloned().unwrap_or(default_mesh);
+    let angvel = sample_sphere(rng) * rng.gen::<f32>() * configuration.max_angular_speed;
+    let restitution = Restitution {
+        coefficient: configuration.restitution,
+        combine_rule: CoefficientCombineRule::Max,
+    };
+    let friction = Friction {
+        coefficient: configuration.friction,
+        combine_rule: CoefficientCombineRule::Average,
+    };
+    let collision_groups = particle_collision_groups(configuration);
+
+    // Prefer recycling an expired particle: reset its state and switch it back on.
+    let mut entity_commands = if let Some(entity) = pool.next() {
+        let mut entity_commands = commands.entity(entity);
+        entity_commands
+            .remove::<(
+                PooledParticle,
+                RigidBodyDisabled,
+                ColliderDisabled,
+                PrivateMaterial,
+                SplashParticle,
+                PairPartner,
+                ImpulseJoint,
+                AsleepSince,
+            )>()
+            .insert((
+                // (nested, as bundle tuples only go up to 15 components)
+                (
+                    SpawnTime(spawn_time),
+                    ExpireTime(expire_time),
+                    BounceCount::default(),
+                    RetireCause::default(),
+                ),
+                ParticleScale(scale),
+                material,
+                mesh,
+                (collider, collision_groups, restitution, friction),
+                ParticleMarker,
+                Velocity { linvel, angvel },
+                PreviousVelocity(linvel),
+                ExternalForce::default(),
+                ExternalImpulse::default(),
+                transform,
+                Visibility::Inherited,
+                TrailPoints::default(),
+            ));
+        entity_commands
+    } else {
+        // Spawn the particle using our Particle bundle struct.
+        let mut entity_commands = commands.spawn(Particle {
+            spawn_time: SpawnTime(spawn_time),
+            expire_time: ExpireTime(expire_time),
+            bounce_count: BounceCount::default(),
+            retire_cause: RetireCause::default(),
+            scale: ParticleScale(scale),
+            marker: ParticleMarker {},
+            velocity: Velocity { linvel, angvel },
+            previous_velocity: PreviousVelocity(linvel),
+            external_force: ExternalForce::default(),
+            external_impulse: ExternalImpulse::default(),
+            active_events: ActiveEvents::COLLISION_EVENTS,
+
+            // Set up the PBR bundle for the geometry that represents the particle
+            geometry: PbrBundle {
+                mesh,
+                transform,
+                material,
+                ..default()
+            },
+        });
+        entity_commands
+            // Insert a dynamic rigid body component for the particle
+            .insert(RigidBody::Dynamic)
+            // Insert a collider component for the particle
+            .insert(collider)
+            // ...and give it the configured bounciness and friction
+            .insert((collision_groups, restitution, friction))
+            // ...and somewhere to record its trail
+            .insert(TrailPoints::default());
+        entity_commands
+    };
+
+    if private_material {
+        entity_commands.insert(PrivateMaterial);
+    }
+
+    let entity = entity_commands.id();
+    spawned.send(ParticleSpawned {
+        entity,
+        position,
+        velocity: linvel,
+    });
+    entity
+}
+
+// burst_particles - an update system that spawns 'burst_size' particles at once when 'B' is
+// pressed, independent of the emitters' timed spawning.
+#[allow(clippy::too_many_arguments)]
+fn burst_particles(
+    configuration: Res<Configuration>,
+    time: Res<Time>,
+    kbd: Res<Input<KeyCode>>,
+    particles: Query<(), With<ParticleMarker>>,
+    pool: Query<Entity, With<PooledParticle>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut rng: ResMut<ParticleRng>,
+    mut spawned: EventWriter<ParticleSpawned>,
     mut commands: Commands,
-    mut query: Query<(Entity, &ExpireTime), With<ParticleMarker>>,
 ) {
-    // Determine if it's time to despawn particles...if so, do it.
-    let now = Instant::now();
-    for (entity, expire_time) in query.iter_mut() {
-        if now >= expire_time.0 {
-            commands.entity(entity).despawn()
+    if !kbd.just_pressed(KeyCode::B) {
+        return;
+    }
+    let rng = &mut rng.0;
+    let mut pool = pool.iter();
+
+    // Never push the population past the cap, even for a burst (unless room is made by
+    // evicting).
+    let count = configuration.room_under_cap(particles.iter().count(), configuration.burst_size);
+
+    let now = time.elapsed_seconds();
+    for _ in 0..count {
+        let (position, direction) = sample_shape(EmitterShape::Point, &configuration, None, rng);
+
+        spawn_particle(
+            &mut commands,
+            &configuration,
+            &mut materials,
+            &mut pool,
+            rng,
+            &mut spawned,
+            position,
+            direction * configuration.speed_distribution.sample(rng),
+            now,
+            now + sample_lifetime(&configuration, configuration.particle_expire_time, rng),
+            None,
+            None,
+        );
+    }
+    info!("Burst: spawned {} particles", count);
+}
+
+// splash_particles - an update system that splashes particles that hit the ground hard enough
+// into a few small, short-lived droplets flying up from where they hit.  Droplets never splash.
+#[allow(clippy::too_many_arguments)]
+fn splash_particles(
+    configuration: Res<Configuration>,
+    time: Res<Time>,
+    mut collisions: EventReader<CollisionEvent>,
+    ground: Query<(), With<Ground>>,
+    splashers: Query<
+        (&Transform, &PreviousVelocity),
+        (With<ParticleMarker>, Without<SplashParticle>),
+    >,
+    particles: Query<(), With<ParticleMarker>>,
+    pool: Query<Entity, With<PooledParticle>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut rng: ResMut<ParticleRng>,
+    mut spawned: EventWriter<ParticleSpawned>,
+    mut commands: Commands,
+) {
+    let rng = &mut rng.0;
+    let mut pool = pool.iter();
+    let now = time.elapsed_seconds();
+    let mut live_particles = particles.iter().count();
+
+    for collision in collisions.read() {
+        // The event doesn't say which entity is which, so try both ways round.
+        let CollisionEvent::Started(entity1, entity2, _) = *collision else {
+            continue;
+        };
+        let particle = if ground.contains(entity2) {
+            entity1
+        } else if ground.contains(entity1) {
+            entity2
+        } else {
+            continue;
+        };
+        let Ok((transform, previous_velocity)) = splashers.get(particle) else {
+            continue;
+        };
+        if -previous_velocity.0.y < configuration.splash_threshold_speed {
+            continue;
+        }
+
+        // Never push the population past the cap, even for a splash (unless room is made by
+        // evicting).
+        let count = configuration.room_under_cap(live_particles, configuration.splash_count);
+        for _ in 0..count {
+            // Fly off in a random direction away from the ground.
+            let mut direction = sample_sphere(rng);
+            direction.y = direction.y.abs();
+            let entity = spawn_particle(
+                &mut commands,
+                &configuration,
+                &mut materials,
+                &mut pool,
+                rng,
+                &mut spawned,
+                transform.translation,
+                direction * SPLASH_SPEED,
+                now,
+                now + SPLASH_EXPIRE_TIME_SECS,
+                None,
+                None,
+            );
+            // Shrink the droplet (overriding the random size spawn_particle gave it).
+            commands.entity(entity).insert((
+                SplashParticle,
+                ParticleScale(SPLASH_SCALE),
+                Transform::from_translation(transform.translation)
+                    .with_scale(Vec3::splat(SPLASH_SCALE)),
+            ));
+        }
+        live_particles += count;
+    }
+}
+
+// record_previous_velocity - an update system that remembers every particle's velocity before
+// the coming physics step, for splash_particles to look at next frame.
+fn record_previous_velocity(
+    mut particles: Query<(&Velocity, &mut PreviousVelocity), With<ParticleMarker>>,
+) {
+    for (velocity, mut previous_velocity) in particles.iter_mut() {
+        previous_velocity.0 = velocity.linvel;
+    }
+}
+
+// log_spawn_rate - an update system that logs how many particles were spawned over each second
+// (and, at trace level, each spawn), as an example of reacting to ParticleSpawned events.
+fn log_spawn_rate(
+    time: Res<Time>,
+    mut spawned: EventReader<ParticleSpawned>,
+    mut count: Local<usize>,
+    mut window_start: Local<f32>,
+) {
+    for event in spawned.read() {
+        trace!(
+            "Spawned {:?} at {} moving at {}",
+            event.entity,
+            event.position,
+            event.velocity
+        );
+        *count += 1;
+    }
+
+    let now = time.elapsed_seconds();
+    if now - *window_start >= 1.0 {
+        debug!("Spawned {} particles/s", *count);
+        *count = 0;
+        *window_start = now;
+    }
+}
+
+// Flash - a component marking a flash where a particle was retired, with the time (in seconds
+// of elapsed app time) it popped up.  Flashes have no physics, don't count as particles and
+// despawn themselves after FLASH_SECS.
+#[derive(Component)]
+struct Flash(f32);
+
+// spawn_flashes - an update system that pops up a flash wherever a particle is retired (but not
+// when clearing everything at once).
+fn spawn_flashes(
+    mut commands: Commands,
+    configuration: Res<Configuration>,
+    time: Res<Time>,
+    mut despawned: EventReader<ParticleDespawned>,
+) {
+    if !configuration.show_flashes {
+        despawned.clear();
+        return;
+    }
+
+    let now = time.elapsed_seconds();
+    for event in despawned.read() {
+        if event.cause == DespawnCause::Cleared {
+            continue;
+        }
+        commands.spawn((
+            PbrBundle {
+                mesh: configuration.flash_mesh.clone(),
+                material: configuration.flash_materials[0].clone(),
+                transform: Transform::from_translation(event.position)
+                    .with_scale(Vec3::splat(FLASH_SCALE_RANGE.0)),
+                ..default()
+            },
+            NotShadowCaster,
+            Flash(now),
+        ));
+    }
+}
+
+// update_flashes - an update system that grows and fades every flash, and despawns the ones
+// that have run their course.
+fn update_flashes(
+    mut commands: Commands,
+    configuration: Res<Configuration>,
+    time: Res<Time>,
+    mut flashes: Query<(
+        Entity,
+        &Flash,
+        &mut Transform,
+        &mut Handle<StandardMaterial>,
+    )>,
+) {
+    let now = time.elapsed_seconds();
+    let (start_scale, end_scale) = FLASH_SCALE_RANGE;
+    for (entity, flash, mut transform, mut material) in flashes.iter_mut() {
+        let t = (now - flash.0) / FLASH_SECS;
+        if t >= 1.0 {
+            commands.entity(entity).despawn();
+            continue;
+        }
+        transform.scale = Vec3::splat(start_scale + (end_scale - start_scale) * t);
+        let step = ((t * FLASH_FADE_STEPS as f32) as usize).min(FLASH_FADE_STEPS - 1);
+        if *material != configuration.flash_materials[step] {
+            *material = configuration.flash_materials[step].clone();
+        }
+    }
+}
+
+// count_despawns - an update system that counts retired particles by cause, as an example of
+// reacting to ParticleDespawned events.
+fn count_despawns(mut despawned: EventReader<ParticleDespawned>, mut stats: ResMut<DespawnStats>) {
+    for event in despawned.read() {
+        trace!(
+            "Retired {:?} at {} ({:?})",
+            event.entity,
+            event.position,
+            event.cause
+        );
+        *stats.0.entry(event.cause).or_default() += 1;
+    }
+}
+
+// print_despawn_stats - an update system that logs how many particles have been retired for each
+// cause when 'D' is pressed.  D also strafes the camera in fly mode, so it's ignored there.
+fn print_despawn_stats(
+    stats: Res<DespawnStats>,
+    kbd: Res<Input<KeyCode>>,
+    camera_mode: Option<Res<CameraMode>>,
+) {
+    if matches!(camera_mode.as_deref(), Some(CameraMode::Fly)) {
+        return;
+    }
+    if kbd.just_pressed(KeyCode::D) {
+        let counts: Vec<String> = DespawnCause::ALL
+            .iter()
+            .map(|cause| format!("{:?} {}", cause, stats.0.get(cause).copied().unwrap_or(0)))
+            .collect();
+        info!("Retired particles: {}", counts.join(", "));
+    }
+}
+
+// cursor_ground_hit - returns where a ray from the camera through the cursor hits the ground, or
+// None if the cursor is outside the window or not over the ground.
+fn cursor_ground_hit(
+    window: &Window,
+    camera: &Camera,
+    camera_transform: &GlobalTransform,
+) -> Option<Vec3> {
+    // Build a ray from the camera through the cursor...
+    let cursor = window.cursor_position()?;
+    let ray = camera.viewport_to_world(camera_transform, cursor)?;
+
+    // ...and find where it hits the ground.
+    let distance = ray.intersect_plane(Vec3::Y * GROUND_HEIGHT, Vec3::Y)?;
+    let hit = ray.get_point(distance);
+    if hit.x.abs() > GROUND_RADIUS || hit.z.abs() > GROUND_RADIUS {
+        return None;
+    }
+    Some(hit)
+}
+
+// click_spawn_particles - an update system that spawns a batch of particles wherever the
+// ground is clicked (left mouse button).  Clicks that miss the ground are ignored.
+#[allow(clippy::too_many_arguments)]
+fn click_spawn_particles(
+    configuration: Res<Configuration>,
+    time: Res<Time>,
+    mouse: Res<Input<MouseButton>>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    cameras: Query<(&Camera, &GlobalTransform), With<Camera3d>>,
+    particles: Query<(), With<ParticleMarker>>,
+    pool: Query<Entity, With<PooledParticle>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut rng: ResMut<ParticleRng>,
+    mut spawned: EventWriter<ParticleSpawned>,
+    mut commands: Commands,
+) {
+    if !mouse.just_pressed(MouseButton::Left) {
+        return;
+    }
+    let rng = &mut rng.0;
+
+    // (there may be no single window or camera, e.g. while the window is closing)
+    let (Ok(window), Ok((camera, camera_transform))) = (windows.get_single(), cameras.get_single())
+    else {
+        return;
+    };
+    let Some(hit) = cursor_ground_hit(window, camera, camera_transform) else {
+        return;
+    };
+
+    // Same all-or-nothing cap check as the emitters.
+    let count = configuration.spawn_count;
+    if !configuration.fits_under_cap(particles.iter().count(), count) {
+        return;
+    }
+
+    let mut pool = pool.iter();
+    let now = time.elapsed_seconds();
+    for _ in 0..count {
+        // Scatter the batch over a small disc just above the ground so the new particles
+        // don't overlap each other or the ground's collider.
+        let (offset, direction) = sample_shape(
+            EmitterShape::Disc { radius: 1.0 },
+            &configuration,
+            None,
+            rng,
+        );
+        spawn_particle(
+            &mut commands,
+            &configuration,
+            &mut materials,
+            &mut pool,
+            rng,
+            &mut spawned,
+            hit + offset + Vec3::Y * configuration.particle_radius * 2.0,
+            direction * configuration.speed_distribution.sample(rng),
+            now,
+            now + sample_lifetime(&configuration, configuration.particle_expire_time, rng),
+            None,
+            None,
+        );
+    }
+}
+
+// sample_shape - returns a random starting offset (relative to the emitter) and a unit launch
+// direction for a new particle, according to the emitter's shape.  Surface emitters need the
+// 'surface' to sample; without one they behave like a point-sized sphere.
+fn sample_shape(
+    shape: EmitterShape,
+    configuration: &Configuration,
+    surface: Option<&SurfaceSampler>,
+    rng: &mut impl Rng,
+) -> (Vec3, Vec3) {
+    match shape {
+        EmitterShape::Point => {
+            // Sample X and Z symmetrically around the emitter.  Keeping the offset inside the
+            // ground's extent stops a big setting from scattering particles off the edge.
+            let max_offset = configuration.max_spawn_offset.min(GROUND_RADIUS);
+            let x = (rng.gen::<f32>() * 2.0 - 1.0) * max_offset;
+            let y = configuration.spawn_height;
+            let z = (rng.gen::<f32>() * 2.0 - 1.0) * max_offset;
+            let direction = sample_direction(configuration, rng);
+            (Vec3::new(x, y, z), direction)
+        }
+        EmitterShape::Sphere { radius } => {
+            let direction = sample_sphere(rng);
+            (direction * radius, direction)
+        }
+        EmitterShape::Ring { radius } => {
+            let phi = rng.gen::<f32>() * std::f32::consts::TAU;
+            let direction = sample_direction(configuration, rng);
+            (Vec3::new(phi.cos(), 0.0, phi.sin()) * radius, direction)
+        }
+        EmitterShape::Disc { radius } => {
+            // Taking the square root of the radius keeps the density even by area instead
+            // of bunching particles up around the center.
+            let r = rng.gen::<f32>().sqrt() * radius;
+            let phi = rng.gen::<f32>() * std::f32::consts::TAU;
+            let direction = sample_direction(configuration, rng);
+            (Vec3::new(phi.cos(), 0.0, phi.sin()) * r, direction)
+        }
+        EmitterShape::Box { half_extents } => {
+            let unit = Vec3::new(rng.gen::<f32>(), rng.gen::<f32>(), rng.gen::<f32>());
+            let direction = sample_direction(configuration, rng);
+            ((unit * 2.0 - Vec3::ONE) * half_extents, direction)
+        }
+        EmitterShape::Surface => match surface.and_then(|surface| surface.sample(rng)) {
+            // Start just off the surface so the particle doesn't spawn inside the mesh's
+            // collider.
+            Some((point, normal)) => (point + normal * SURFACE_OFFSET, normal),
+            None => (Vec3::ZERO, sample_sphere(rng)),
+        },
+    }
+}
+
+// sample_sphere - returns a random unit vector distributed uniformly over the sphere.
+fn sample_sphere(rng: &mut impl Rng) -> Vec3 {
+    // Sampling the height uniformly (rather than the latitude) keeps the points from
+    // clustering at the poles (Archimedes' hat-box theorem).
+    let y = rng.gen::<f32>() * 2.0 - 1.0;
+    let r = (1.0 - y * y).max(0.0).sqrt();
+    let phi = rng.gen::<f32>() * std::f32::consts::TAU;
+    Vec3::new(r * phi.cos(), y, r * phi.sin())
+}
+
+// sample_direction - returns a random unit launch direction according to the configured emission
+// shape.
+fn sample_direction(configuration: &Configuration, rng: &mut impl Rng) -> Vec3 {
+    let axis = configuration.emission_axis.normalize();
+    match configuration.emission_shape {
+        EmissionShape::Point => axis,
+        EmissionShape::Cone => sample_cone(axis, configuration.spread_angle, rng),
+        EmissionShape::Disk { radius } => {
+            // Pick a point on the disc (sqrt keeps it uniform by area) in the plane
+            // perpendicular to +Y, rotate it to sit across the axis, and aim at it.
+            let r = rng.gen::<f32>().sqrt() * radius;
+            let phi = rng.gen::<f32>() * std::f32::consts::TAU;
+            let point = Vec3::new(r * phi.cos(), 1.0, r * phi.sin());
+            (Quat::from_rotation_arc(Vec3::Y, axis) * point).normalize()
+        }
+        EmissionShape::Sphere => sample_sphere(rng),
+        EmissionShape::Legacy => {
+            let x = (rng.gen::<f32>() * 2.0 - 1.0) * LEGACY_JITTER;
+            let z = (rng.gen::<f32>() * 2.0 - 1.0) * LEGACY_JITTER;
+            (Quat::from_rotation_arc(Vec3::Y, axis) * Vec3::new(x, 1.0, z)).normalize()
+        }
+    }
+}
+
+// sample_cone - returns a random unit vector distributed uniformly (by solid angle) inside
+// the cone around 'axis' with the full apex angle 'spread_angle'.
+fn sample_cone(axis: Vec3, spread_angle: f32, rng: &mut impl Rng) -> Vec3 {
+    let half_angle = spread_angle.clamp(0.0, std::f32::consts::PI) / 2.0;
+
+    // Sampling cos(theta) uniformly between cos(half_angle) and 1 gives an even spread
+    // over the spherical cap (sampling theta directly would cluster around the axis).
+    let cos_theta = 1.0 - rng.gen::<f32>() * (1.0 - half_angle.cos());
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = rng.gen::<f32>() * std::f32::consts::TAU;
+
+    // Build the direction around +Y, then rotate it onto the requested axis.
+    let direction = Vec3::new(sin_theta * phi.cos(), cos_theta, sin_theta * phi.sin());
+    Quat::from_rotation_arc(Vec3::Y, axis.normalize()) * direction
+}
+
+// spiral_direction - returns the unit vector on the rim of the cone around 'axis' with the full
+// apex angle 'spread_angle', at azimuth 'phase' around the axis.
+fn spiral_direction(axis: Vec3, spread_angle: f32, phase: f32) -> Vec3 {
+    let half_angle = spread_angle.clamp(0.0, std::f32::consts::PI) / 2.0;
+    let direction = Vec3::new(
+        half_angle.sin() * phase.cos(),
+        half_angle.cos(),
+        half_angle.sin() * phase.sin(),
+    );
+    Quat::from_rotation_arc(Vec3::Y, axis.normalize()) * direction
+}
+
+// adjust_spread_angle - an update system that widens ('[') or narrows (']') the emission cone,
+// from a straight column (0) up to a hemisphere.  Only new particles are affected.
+fn adjust_spread_angle(
+    mut configuration: ResMut<Configuration>,
+    time: Res<Time<Real>>,
+    kbd: Res<Input<KeyCode>>,
+    mut notice: ResMut<StatusNotice>,
+) {
+    let step = if kbd.just_pressed(KeyCode::BracketLeft) {
+        SPREAD_ANGLE_STEP
+    } else if kbd.just_pressed(KeyCode::BracketRight) {
+        -SPREAD_ANGLE_STEP
+    } else {
+        return;
+    };
+
+    configuration.spread_angle =
+        (configuration.spread_angle + step).clamp(0.0, std::f32::consts::PI);
+    let message = format!(
+        "Spread angle: {:.1} degrees",
+        configuration.spread_angle.to_degrees()
+    );
+    info!("{}", message);
+    notice.show(message, time.elapsed_seconds());
+}
+
+// adjust_spawn_rate - an update system that makes every emitter busier ('=' halves the time
+// between batches) or calmer ('-' doubles it).
+fn adjust_spawn_rate(time: Res<Time>, kbd: Res<Input<KeyCode>>, mut emitters: Query<&mut Emitter>) {
+    let factor = if kbd.just_pressed(KeyCode::Equals) {
+        0.5
+    } else if kbd.just_pressed(KeyCode::Minus) {
+        2.0
+    } else {
+        return;
+    };
+
+    let now = time.elapsed_seconds();
+    for mut emitter in emitters.iter_mut() {
+        scale_spawn_delta(&mut emitter, factor, now);
+        info!(
+            "Spawn rate: {} particles every {} ms ({:.0} particles/s)",
+            emitter.spawn_count,
+            emitter.spawn_delta.as_millis(),
+            emitter.rate
+        );
+    }
+}
+
+// adjust_spawn_count - an update system that multiplies (numpad '+') or divides (numpad '-') the
+// number of particles per batch by SPAWN_COUNT_STEP.  The emitters pick it up from their next
+// batch on.
+fn adjust_spawn_count(
+    mut configuration: ResMut<Configuration>,
+    time: Res<Time>,
+    kbd: Res<Input<KeyCode>>,
+    mut emitters: Query<&mut Emitter>,
+) {
+    let factor = if kbd.just_pressed(KeyCode::NumpadAdd) {
+        SPAWN_COUNT_STEP
+    } else if kbd.just_pressed(KeyCode::NumpadSubtract) {
+        1.0 / SPAWN_COUNT_STEP
+    } else {
+        return;
+    };
+
+    let previous = configuration.clone();
+    configuration.spawn_count =
+        ((previous.spawn_count as f32 * factor).round() as usize).clamp(1, MAX_SPAWN_COUNT);
+    retune_emitters(
+        &previous,
+        &configuration,
+        &mut emitters,
+        time.elapsed_seconds(),
+    );
+    info!("Spawn count: {}", configuration.spawn_count);
+}
+
+// scale_spawn_delta - multiplies an emitter's batch interval by 'factor' (within the allowed
+// range), and its continuous rate to match.
+fn scale_spawn_delta(emitter: &mut Emitter, factor: f32, now: f32) {
+    let min = Duration::from_millis(MIN_SPAWN_DELTA_MS);
+    let max = Duration::from_millis(MAX_SPAWN_DELTA_MS);
+    let spawn_delta = emitter.spawn_delta.mul_f32(factor).clamp(min, max);
+
+    // Keep the continuous rate in step with the batch interval.
+    emitter.rate *= emitter.spawn_delta.as_secs_f32() / spawn_delta.as_secs_f32();
+    emitter.spawn_delta = spawn_delta;
+
+    // Don't let a deadline computed from the old (longer) delta hold up a speed-up.
+    emitter.next_spawn = emitter.next_spawn.min(now + spawn_delta.as_secs_f32());
+}
+
+// cycle_particle_coloring - an update system that switches to the next coloring option when 'C'
+// is pressed.  Particles that are already alive keep the material they were spawned with.
+fn cycle_particle_coloring(mut configuration: ResMut<Configuration>, kbd: Res<Input<KeyCode>>) {
+    if kbd.just_pressed(KeyCode::C) {
+        configuration.coloring = match configuration.coloring {
+            ParticleColoring::Uniform => ParticleColoring::Speed,
+            ParticleColoring::Speed => ParticleColoring::Height,
+            ParticleColoring::Height => ParticleColoring::RandomHue,
+            ParticleColoring::RandomHue => ParticleColoring::Age,
+            ParticleColoring::Age => ParticleColoring::Uniform,
+        };
+        info!("Particle coloring: {:?}", configuration.coloring);
+    }
+}
+
+// color_particles_by_speed - an update system that tints each particle from blue (slow) to red
+// (fast) according to its current speed.
+fn color_particles_by_speed(
+    configuration: Res<Configuration>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    query: Query<
+        (&Velocity, &Handle<StandardMaterial>),
+        (With<ParticleMarker>, With<PrivateMaterial>),
+    >,
+) {
+    if configuration.coloring != ParticleColoring::Speed {
+        return;
+    }
+
+    for (velocity, handle) in query.iter() {
+        if let Some(material) = materials.get_mut(handle) {
+            let t =
+                (velocity.linvel.length() - SPEED_COLOR_MIN) / (SPEED_COLOR_MAX - SPEED_COLOR_MIN);
+            recolor(
+                material,
+                lerp_color(Color::BLUE, Color::RED, t),
+                configuration.emissive_strength,
+            );
+        }
+    }
+}
+
+// color_particles_by_height - an update system that tints each particle according to its height
+// above the ground.
+fn color_particles_by_height(
+    configuration: Res<Configuration>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    query: Query<
+        (&Transform, &Handle<StandardMaterial>),
+        (With<ParticleMarker>, With<PrivateMaterial>),
+    >,
+) {
+    if configuration.coloring != ParticleColoring::Height {
+        return;
+    }
+
+    for (transform, handle) in query.iter() {
+        if let Some(material) = materials.get_mut(handle) {
+            let t = (transform.translation.y - GROUND_HEIGHT) / (HEIGHT_COLOR_MAX - GROUND_HEIGHT);
+            recolor(
+                material,
+                lerp_color(
+                    configuration.low_height_color,
+                    configuration.high_height_color,
+                    t,
+                ),
+                configuration.emissive_strength,
+            );
+        }
+    }
+}
+
+// color_particles_by_age - an update system that tints each particle from the start color to
+// the end color over its life.  Only the color changes, so a fade-out's alpha is left alone.
+fn color_particles_by_age(
+    configuration: Res<Configuration>,
+    time: Res<Time>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    query: Query<
+        (&SpawnTime, &ExpireTime, &Handle<StandardMaterial>),
+        (With<ParticleMarker>, With<PrivateMaterial>),
+    >,
+) {
+    if configuration.coloring != ParticleColoring::Age {
+        return;
+    }
+
+    let now = time.elapsed_seconds();
+    for (spawn_time, expire_time, handle) in query.iter() {
+        if let Some(material) = materials.get_mut(handle) {
+            let lifetime = expire_time.0 - spawn_time.0;
+            let t = if lifetime > 0.0 {
+                (now - spawn_time.0) / lifetime
+            } else {
+                1.0
+            };
+            recolor(
+                material,
+                lerp_color(configuration.start_color, configuration.end_color, t),
+                configuration.emissive_strength,
+            );
         }
     }
 }
+
+// update_particle_shadows - an update system that decides which particles cast shadows: none
+// when particle shadows are off, otherwise those within the shadow caster radius of the camera
+// (or all of them).  Everything else, particles included, keeps receiving shadows.
+fn update_particle_shadows(
+    configuration: Res<Configuration>,
+    mut commands: Commands,
+    camera: Query<&GlobalTransform, With<Camera3d>>,
+    particles: Query<(Entity, &Transform, Has<NotShadowCaster>), With<ParticleMarker>>,
+) {
+    // Without a camera (e.g. headless) there's nothing to measure the radius from.
+    let camera = camera.get_single().ok().map(|camera| camera.translation());
+    let radius = configuration.shadow_caster_radius;
+
+    for (entity, transform, not_caster) in particles.iter() {
+        let casts = configuration.particle_shadows
+            && match (radius, camera) {
+                (Some(radius), Some(camera)) => {
+                    transform.translation.distance_squared(camera) <= radius * radius
+                }
+                _ => true,
+            };
+        // Only touch the particles that change, so the renderer isn't kept busy for nothing.
+        if casts && not_caster {
+            commands.entity(entity).remove::<NotShadowCaster>();
+        } else if !casts && !not_caster {
+            commands.entity(entity).insert(NotShadowCaster);
+        }
+    }
+}
+
+// recolor - gives a particle's (private) material the color 'color', glowing with it at strength
+// 'glow'.  The alpha is left alone so it doesn't fight a fade-out.
+fn recolor(material: &mut StandardMaterial, color: Color, glow: f32) {
+    material.base_color = color.with_a(material.base_color.a());
+    material.emissive = color.with_a(1.0) * glow;
+}
+
+// update_particle_glow - an update system that makes the shared particle materials glow as
+// configured, whenever the configuration changes (including when it's first added).  Private
+// materials copy the glow from these when they're made.
+fn update_particle_glow(
+    configuration: Res<Configuration>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    if !configuration.is_changed() {
+        return;
+    }
+
+    let strength = configuration.emissive_strength;
+    if let Some(material) = materials.get_mut(&configuration.particle_material) {
+        material.emissive = configuration.emissive_color * strength;
+    }
+    for handle in &configuration.palette_materials {
+        if let Some(material) = materials.get_mut(handle) {
+            material.emissive = material.base_color * strength;
+        }
+    }
+}
+
+// cycle_death_effect - an update system that switches to the next death effect when 'V' is
+// pressed.  Particles that are already alive keep the material they were spawned with.
+fn cycle_death_effect(mut configuration: ResMut<Configuration>, kbd: Res<Input<KeyCode>>) {
+    if kbd.just_pressed(KeyCode::V) {
+        configuration.death_effect = match configuration.death_effect {
+            DeathEffect::None => DeathEffect::Fade,
+            DeathEffect::Fade => DeathEffect::Shrink,
+            DeathEffect::Shrink => DeathEffect::None,
+        };
+        info!("Death effect: {:?}", configuration.death_effect);
+    }
+}
+
+// toggle_despawn_on_ground_hit - an update system that switches retiring particles as soon as
+// they hit the ground on or off when 'H' is pressed.
+fn toggle_despawn_on_ground_hit(
+    mut configuration: ResMut<Configuration>,
+    kbd: Res<Input<KeyCode>>,
+) {
+    if kbd.just_pressed(KeyCode::H) {
+        configuration.despawn_on_ground_hit = !configuration.despawn_on_ground_hit;
+        info!(
+            "Despawn on ground hit: {}",
+            if configuration.despawn_on_ground_hit {
+                "on"
+            } else {
+                "off"
+            }
+        );
+    }
+}
+
+// despawn_on_ground_hit - an update system that, when enabled, makes particles expire the moment
+// they first touch the ground.  It only moves their expire time up to now, so despawn_particles
+// (which runs right after) retires them the usual way.
+fn despawn_on_ground_hit(
+    configuration: Res<Configuration>,
+    time: Res<Time>,
+    mut collisions: EventReader<CollisionEvent>,
+    ground: Query<(), With<Ground>>,
+    mut particles: Query<(&mut ExpireTime, &mut RetireCause), With<ParticleMarker>>,
+) {
+    if !configuration.despawn_on_ground_hit {
+        // Drop the events so they don't pile up for when it's switched on.
+        collisions.clear();
+        return;
+    }
+
+    let now = time.elapsed_seconds();
+    for collision in collisions.read() {
+        let CollisionEvent::Started(entity1, entity2, _) = *collision else {
+            continue;
+        };
+        // The event doesn't say which entity is which, so try both ways round.
+        let particle = if ground.contains(entity2) {
+            entity1
+        } else if ground.contains(entity1) {
+            entity2
+        } else {
+            continue;
+        };
+        if let Ok((mut expire_time, mut cause)) = particles.get_mut(particle) {
+            retire_early(&mut expire_time, &mut cause, now, DespawnCause::HitGround);
+        }
+    }
+}
+
+// despawn_after_bounces - an update system that counts each particle's bounces off the ground
+// and, when there's a limit, makes it expire on the bounce after the last one allowed.  Like
+// despawn_on_ground_hit it only moves the expire time up to now.
+fn despawn_after_bounces(
+    configuration: Res<Configuration>,
+    time: Res<Time>,
+    mut collisions: EventReader<CollisionEvent>,
+    ground: Query<(), With<Ground>>,
+    mut particles: Query<
+        (&mut BounceCount, &mut ExpireTime, &mut RetireCause),
+        With<ParticleMarker>,
+    >,
+) {
+    let now = time.elapsed_seconds();
+    for collision in collisions.read() {
+        let CollisionEvent::Started(entity1, entity2, _) = *collision else {
+            continue;
+        };
+        // Only contacts with the ground count; particles hitting each other don't.
+        let particle = if ground.contains(entity2) {
+            entity1
+        } else if ground.contains(entity1) {
+            entity2
+        } else {
+            continue;
+        };
+        let Ok((mut bounces, mut expire_time, mut cause)) = particles.get_mut(particle) else {
+            continue;
+        };
+        bounces.0 += 1;
+        if configuration
+            .max_bounces
+            .is_some_and(|max_bounces| bounces.0 > max_bounces)
+        {
+            retire_early(&mut expire_time, &mut cause, now, DespawnCause::Bounced);
+        }
+    }
+}
+
+// despawn_sleeping - an update system that, when a grace period is configured, makes particles
+// expire once their rigid body has slept for that long.  Like despawn_on_ground_hit it only
+// brings the expire time forward (leaving room for the death effect), so whichever comes first
+// wins and despawn_particles does the retiring.
+fn despawn_sleeping(
+    configuration: Res<Configuration>,
+    time: Res<Time>,
+    context: Res<RapierContext>,
+    mut commands: Commands,
+    mut particles: Query<
+        (
+            Entity,
+            &RapierRigidBodyHandle,
+            &mut ExpireTime,
+            &mut RetireCause,
+            Option<&AsleepSince>,
+        ),
+        With<ParticleMarker>,
+    >,
+) {
+    let Some(grace_period) = configuration.sleep_grace_period else {
+        return;
+    };
+    // Leave time for the death effect to play out, rather than cutting it short.
+    let linger = match configuration.death_effect {
+        DeathEffect::None => 0.0,
+        DeathEffect::Fade => configuration.fade_out_time,
+        DeathEffect::Shrink => configuration.shrink_time,
+    };
+
+    let now = time.elapsed_seconds();
+    for (entity, handle, mut expire_time, mut cause, asleep_since) in particles.iter_mut() {
+        let sleeping = context
+            .bodies
+            .get(handle.0)
+            .is_some_and(|body| body.is_sleeping());
+        match (sleeping, asleep_since) {
+            (true, None) => {
+                commands.entity(entity).insert(AsleepSince(now));
+            }
+            (true, Some(since)) if now - since.0 >= grace_period => {
+                retire_early(
+                    &mut expire_time,
+                    &mut cause,
+                    now + linger,
+                    DespawnCause::Slept,
+                );
+            }
+            (false, Some(_)) => {
+                commands.entity(entity).remove::<AsleepSince>();
+            }
+            _ => {}
+        }
+    }
+}
+
+// despawn_below_kill_plane - an update system that makes particles that have fallen below the
+// kill plane expire now, so they don't fall (and get simulated) forever.  Like
+// despawn_on_ground_hit it leaves the actual retiring to despawn_particles.
+fn despawn_below_kill_plane(
+    configuration: Res<Configuration>,
+    time: Res<Time>,
+    mut stats: ResMut<CollisionStats>,
+    mut particles: Query<(&Transform, &mut ExpireTime, &mut RetireCause), With<ParticleMarker>>,
+) {
+    let now = time.elapsed_seconds();
+    let mut removed = 0;
+    for (transform, mut expire_time, mut cause) in particles.iter_mut() {
+        if transform.translation.y < configuration.kill_plane_y
+            && retire_early(
+                &mut expire_time,
+                &mut cause,
+                now,
+                DespawnCause::FellOffWorld,
+            )
+        {
+            removed += 1;
+        }
+    }
+
+    if removed > 0 {
+        stats.kill_plane_removals += removed;
+    }
+}
+
+// count_ground_collisions - an update system that counts every time a particle starts touching
+// the ground.
+fn count_ground_collisions(
+    mut stats: ResMut<CollisionStats>,
+    mut collisions: EventReader<CollisionEvent>,
+    ground: Query<(), With<Ground>>,
+    particles: Query<(), With<ParticleMarker>>,
+) {
+    let mut hits = 0;
+    for collision in collisions.read() {
+        // Only count the start of a contact, and only between a particle and the ground (in
+        // either order).
+        let CollisionEvent::Started(entity1, entity2, _) = *collision else {
+            continue;
+        };
+        if (ground.contains(entity1) && particles.contains(entity2))
+            || (ground.contains(entity2) && particles.contains(entity1))
+        {
+            hits += 1;
+        }
+    }
+
+    // Only touch the resource when there's something to add, so the counter text isn't
+    // rebuilt every frame.
+    if hits > 0 {
+        stats.ground_hits += hits;
+    }
+}
+
+// fade_particles - an update system that fades particles out over the last
+// Configuration::fade_out_time seconds of their life.  The alpha reaches zero right as
+// despawn_particles retires them.
+fn fade_particles(
+    configuration: Res<Configuration>,
+    time: Res<Time>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    query: Query<
+        (&ExpireTime, &Handle<StandardMaterial>),
+        (With<ParticleMarker>, With<PrivateMaterial>),
+    >,
+) {
+    let fade_out_time = configuration.fade_out_time;
+    if configuration.death_effect != DeathEffect::Fade || fade_out_time <= 0.0 {
+        return;
+    }
+
+    let now = time.elapsed_seconds();
+    for (expire_time, handle) in query.iter() {
+        // Only touch the material once the particle has actually started fading.
+        let alpha = ((expire_time.0 - now) / fade_out_time).clamp(0.0, 1.0);
+        if alpha < 1.0 {
+            if let Some(material) = materials.get_mut(handle) {
+                material.base_color.set_a(alpha);
+            }
+        }
+    }
+}
+
+// shrink_particles - an update system that shrinks particles over the last
+// Configuration::shrink_time seconds of their life.  The collider scales along with the
+// transform, and is switched off entirely once the particle gets tiny so it can't wedge itself
+// between its neighbours.  despawn_particles still does the final removal, on time, whether or
+// not the particle has finished shrinking.
+fn shrink_particles(
+    configuration: Res<Configuration>,
+    time: Res<Time>,
+    mut commands: Commands,
+    mut query: Query<(Entity, &ExpireTime, &ParticleScale, &mut Transform), With<ParticleMarker>>,
+) {
+    let shrink_time = configuration.shrink_time;
+    if configuration.death_effect != DeathEffect::Shrink || shrink_time <= 0.0 {
+        return;
+    }
+
+    let now = time.elapsed_seconds();
+    for (entity, expire_time, particle_scale, mut transform) in query.iter_mut() {
+        let scale = ((expire_time.0 - now) / shrink_time).clamp(0.0, 1.0);
+        if scale < 1.0 {
+            transform.scale = Vec3::splat(particle_scale.0 * scale);
+            if scale < SHRINK_COLLIDER_MIN_SCALE {
+                commands.entity(entity).insert(ColliderDisabled);
+            }
+        }
+    }
+}
+
+// lerp_color - linearly interpolates between two colors.  't' is clamped to [0, 1].
+fn lerp_color(from: Color, to: Color, t: f32) -> Color {
+    let t = t.clamp(0.0, 1.0);
+    let from = Vec4::from(from.as_rgba_f32());
+    let to = Vec4::from(to.as_rgba_f32());
+    let [r, g, b, a] = from.lerp(to, t).to_array();
+    Color::rgba(r, g, b, a)
+}
+
+// print_spawn_stats - an update system that logs the speed statistics of the last spawned batch
+// when 'I' is pressed.
+fn print_spawn_stats(spawn_stats: Res<SpawnStats>, kbd: Res<Input<KeyCode>>) {
+    if kbd.just_pressed(KeyCode::I) {
+        info!(
+            "Last batch: {} particles, speed min {:.2} / max {:.2} / mean {:.2}",
+            spawn_stats.count, spawn_stats.min_speed, spawn_stats.max_speed, spawn_stats.mean_speed
+        );
+    }
+}
+
+// toggle_particle_shape - an update system that switches new particles between spheres and
+// cubes when 'K' is pressed.
+fn toggle_particle_shape(mut configuration: ResMut<Configuration>, kbd: Res<Input<KeyCode>>) {
+    if kbd.just_pressed(KeyCode::K) {
+        configuration.particle_shape = match configuration.particle_shape {
+            ParticleShape::Sphere => ParticleShape::Cube,
+            ParticleShape::Cube => ParticleShape::Sphere,
+        };
+        info!("Particle shape: {:?}", configuration.particle_shape);
+    }
+}
+
+// toggle_spawn_ramp - an update system that starts (or cancels) a spawn ramp on every emitter
+// when 'R' is pressed.  Together with the FPS counter this shows where the frame rate collapses.
+fn toggle_spawn_ramp(time: Res<Time>, kbd: Res<Input<KeyCode>>, mut emitters: Query<&mut Emitter>) {
+    if !kbd.just_pressed(KeyCode::R) {
+        return;
+    }
+
+    for mut emitter in emitters.iter_mut() {
+        emitter.ramp = match emitter.ramp {
+            Some(_) => None,
+            None => Some(SpawnRamp {
+                initial_rate: RAMP_INITIAL_RATE,
+                target_rate: RAMP_TARGET_RATE,
+                duration: RAMP_DURATION_SECS,
+                start_time: time.elapsed_seconds(),
+            }),
+        };
+    }
+    info!("Spawn ramp toggled (continuous emission mode only)");
+}
+
+// toggle_pause - an update system that freezes or resumes the whole simulation when the
+// spacebar is pressed.  Pausing the virtual clock stops the particles' lifetimes (and everything
+// else timed off it) from running out while paused, and the physics pipeline is stopped too.
+fn toggle_pause(
+    mut paused: ResMut<Paused>,
+    mut rapier_configuration: ResMut<RapierConfiguration>,
+    mut time: ResMut<Time<Virtual>>,
+    kbd: Res<Input<KeyCode>>,
+) {
+    if kbd.just_pressed(KeyCode::Space) {
+        paused.0 = !paused.0;
+        rapier_configuration.physics_pipeline_active = !paused.0;
+        if paused.0 {
+            time.pause();
+        } else {
+            time.unpause();
+        }
+        info!("{}", if paused.0 { "Paused" } else { "Resumed" });
+    }
+}
+
+// take_screenshot - an update system that saves a PNG of the window when F2 is pressed, named
+// after the time it was taken (e.g. screenshot-1700000000123.png) in the working directory.
+fn take_screenshot(
+    kbd: Res<Input<KeyCode>>,
+    windows: Query<Entity, With<PrimaryWindow>>,
+    mut screenshot_manager: ResMut<ScreenshotManager>,
+) {
+    if !kbd.just_pressed(KeyCode::F2) {
+        return;
+    }
+    let Ok(window) = windows.get_single() else {
+        return;
+    };
+
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let path = format!("screenshot-{}.png", millis);
+    match screenshot_manager.save_screenshot_to_disk(window, &path) {
+        Ok(()) => info!("Saving screenshot to {}", path),
+        Err(error) => warn!("Couldn't take a screenshot: {}", error),
+    }
+}
+
+// clear_particles - an update system that despawns every live (and pooled) particle when Delete
+// is pressed, and restarts the emitters' schedules so spawning picks up cleanly from now.
+fn clear_particles(
+    mut commands: Commands,
+    time: Res<Time>,
+    kbd: Res<Input<KeyCode>>,
+    particles: Query<
+        (Entity, &Transform, Has<ParticleMarker>),
+        Or<(With<ParticleMarker>, With<PooledParticle>)>,
+    >,
+    mut emitters: Query<&mut Emitter>,
+    mut despawned: EventWriter<ParticleDespawned>,
+) {
+    if !kbd.just_pressed(KeyCode::Delete) {
+        return;
+    }
+
+    let mut removed = 0;
+    for (entity, transform, live) in particles.iter() {
+        // Pooled particles were already retired (and reported) once.
+        if live {
+            despawned.send(ParticleDespawned {
+                entity,
+                position: transform.translation,
+                cause: DespawnCause::Cleared,
+            });
+        }
+        commands.entity(entity).despawn();
+        removed += 1;
+    }
+
+    let now = time.elapsed_seconds();
+    for mut emitter in emitters.iter_mut() {
+        emitter.next_spawn = now;
+        emitter.accumulator = 0.0;
+    }
+    info!("Cleared {} particles", removed);
+}
+
+// adjust_ambient_light - an update system that lowers (F5) or raises (F6) the brightness of the
+// ambient light.
+fn adjust_ambient_light(mut configuration: ResMut<Configuration>, kbd: Res<Input<KeyCode>>) {
+    let step = if kbd.just_pressed(KeyCode::F5) {
+        -AMBIENT_BRIGHTNESS_STEP
+    } else if kbd.just_pressed(KeyCode::F6) {
+        AMBIENT_BRIGHTNESS_STEP
+    } else {
+        return;
+    };
+
+    configuration.ambient_brightness =
+        (configuration.ambient_brightness + step).clamp(0.0, MAX_AMBIENT_BRIGHTNESS);
+    info!(
+        "Ambient brightness: {:.2}",
+        configuration.ambient_brightness
+    );
+}
+
+// fog_settings - returns the camera fog the configuration asks for.
+fn fog_settings(configuration: &Configuration) -> FogSettings {
+    FogSettings {
+        color: configuration.fog_color,
+        falloff: FogFalloff::Linear {
+            start: configuration.fog_start,
+            end: configuration.fog_end,
+        },
+        ..default()
+    }
+}
+
+// update_fog - an update system that passes changes to the fog settings on to the camera,
+// adding or removing its fog as it's switched on or off.
+fn update_fog(
+    configuration: Res<Configuration>,
+    mut commands: Commands,
+    mut cameras: Query<(Entity, Option<&mut FogSettings>), With<Camera3d>>,
+) {
+    if !configuration.is_changed() {
+        return;
+    }
+
+    for (camera, fog) in cameras.iter_mut() {
+        match (configuration.show_fog, fog) {
+            (true, Some(mut fog)) => *fog = fog_settings(&configuration),
+            (true, None) => {
+                commands.entity(camera).insert(fog_settings(&configuration));
+            }
+            (false, Some(_)) => {
+                commands.entity(camera).remove::<FogSettings>();
+            }
+            (false, None) => {}
+        }
+    }
+}
+
+// update_ambient_light - an update system that passes changes to the ambient light settings on
+// to the AmbientLight resource.  While the sun is moving, move_sun sets the ambient light instead.
+fn update_ambient_light(
+    configuration: Res<Configuration>,
+    mut ambient_light: ResMut<AmbientLight>,
+) {
+    let cycling = configuration.lighting == Lighting::Sun && configuration.day_length > 0.0;
+    if !configuration.is_changed() || cycling {
+        return;
+    }
+    ambient_light.color = configuration.ambient_color;
+    ambient_light.brightness = configuration.ambient_brightness;
+}
+
+// adjust_time_scale - an update system that halves (F3) or doubles (F4) the speed of the
+// simulation.  It scales the virtual clock, which Rapier steps by and particle lifetimes are
+// measured on, so at 0.25x particles fly four times slower but still live just as long in
+// simulated seconds.  Rapier's timestep is scaled to match (see physics_timestep).
+fn adjust_time_scale(
+    mut time_scale: ResMut<TimeScale>,
+    mut time: ResMut<Time<Virtual>>,
+    mut rapier_configuration: ResMut<RapierConfiguration>,
+    kbd: Res<Input<KeyCode>>,
+) {
+    let factor = if kbd.just_pressed(KeyCode::F3) {
+        0.5
+    } else if kbd.just_pressed(KeyCode::F4) {
+        2.0
+    } else {
+        return;
+    };
+
+    let (min_scale, max_scale) = TIME_SCALE_RANGE;
+    time_scale.0 = (time_scale.0 * factor).clamp(min_scale, max_scale);
+    time.set_relative_speed(time_scale.0);
+    rapier_configuration.timestep_mode = physics_timestep(time_scale.0);
+    info!("Time scale: {}x", time_scale.0);
+}
+
+// physics_timestep - returns the Rapier timestep mode for running at 'time_scale'.  Rapier steps
+// by the virtual clock's delta, which is already scaled, so its own time_scale stays at 1; but it
+// caps each step at max_dt, which would hold anything above 1x back to normal speed while the
+// lifetimes ran faster.  So the cap is scaled too, and the step split into enough substeps that
+// none is longer than at 1x.
+fn physics_timestep(time_scale: f32) -> TimestepMode {
+    TimestepMode::Variable {
+        max_dt: PHYSICS_MAX_DT * time_scale,
+        time_scale: 1.0,
+        substeps: time_scale.ceil().max(1.0) as usize,
+    }
+}
+
+// adjust_gravity - an update system that makes gravity stronger (PageUp) or weaker (PageDown),
+// and toggles zero-g ('G').
+fn adjust_gravity(
+    mut rapier_configuration: ResMut<RapierConfiguration>,
+    kbd: Res<Input<KeyCode>>,
+    mut saved_gravity: Local<Option<Vec3>>,
+) {
+    let gravity = rapier_configuration.gravity;
+    let new_gravity = if kbd.just_pressed(KeyCode::G) {
+        // Remember the normal gravity while floating so it can be restored.
+        match saved_gravity.take() {
+            Some(saved) => saved,
+            None => {
+                *saved_gravity = Some(gravity);
+                Vec3::ZERO
+            }
+        }
+    } else if kbd.just_pressed(KeyCode::PageUp) || kbd.just_pressed(KeyCode::PageDown) {
+        if saved_gravity.is_some() {
+            // No gravity to scale in zero-g.
+            return;
+        }
+        let factor = if kbd.just_pressed(KeyCode::PageUp) {
+            GRAVITY_STEP
+        } else {
+            1.0 / GRAVITY_STEP
+        };
+        let y = (gravity.y * factor).abs().clamp(MIN_GRAVITY, MAX_GRAVITY);
+        Vec3::new(gravity.x, -y, gravity.z)
+    } else {
+        return;
+    };
+
+    rapier_configuration.gravity = new_gravity;
+    info!("Gravity: {:.2}", new_gravity.y);
+}
+
+// adjust_restitution - an update system that makes new particles bouncier ('.') or less bouncy
+// (',').  Particles that are already alive keep their bounciness.
+fn adjust_restitution(
+    mut configuration: ResMut<Configuration>,
+    time: Res<Time<Real>>,
+    kbd: Res<Input<KeyCode>>,
+    mut notice: ResMut<StatusNotice>,
+) {
+    let step = if kbd.just_pressed(KeyCode::Period) {
+        RESTITUTION_STEP
+    } else if kbd.just_pressed(KeyCode::Comma) {
+        -RESTITUTION_STEP
+    } else {
+        return;
+    };
+
+    configuration.restitution = (configuration.restitution + step).clamp(0.0, 1.0);
+    let message = format!("Restitution: {:.1}", configuration.restitution);
+    info!("{}", message);
+    notice.show(message, time.elapsed_seconds());
+}
+
+// adjust_friction - an update system that raises (''') or lowers (';') the friction of the
+// ground and of new particles, so they stop rolling sooner or later.
+fn adjust_friction(
+    mut configuration: ResMut<Configuration>,
+    time: Res<Time<Real>>,
+    kbd: Res<Input<KeyCode>>,
+    mut notice: ResMut<StatusNotice>,
+    mut ground: Query<&mut Friction, With<Ground>>,
+) {
+    let step = if kbd.just_pressed(KeyCode::Apostrophe) {
+        FRICTION_STEP
+    } else if kbd.just_pressed(KeyCode::Semicolon) {
+        -FRICTION_STEP
+    } else {
+        return;
+    };
+
+    configuration.friction = (configuration.friction + step).clamp(0.0, MAX_FRICTION);
+    for mut friction in &mut ground {
+        friction.coefficient = configuration.friction;
+    }
+    let message = format!("Friction: {:.1}", configuration.friction);
+    info!("{}", message);
+    notice.show(message, time.elapsed_seconds());
+}
+
+// adjust_wind - an update system that changes the wind's X (left / right arrows) and Z (up / down
+// arrows) force.  With Ctrl held the arrows steer the nozzle instead.
+fn adjust_wind(mut wind: ResMut<Wind>, kbd: Res<Input<KeyCode>>) {
+    if kbd.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) {
+        return;
+    }
+
+    let mut step = Vec3::ZERO;
+    if kbd.just_pressed(KeyCode::Left) {
+        step.x -= WIND_STEP;
+    }
+    if kbd.just_pressed(KeyCode::Right) {
+        step.x += WIND_STEP;
+    }
+    if kbd.just_pressed(KeyCode::Up) {
+        step.z -= WIND_STEP;
+    }
+    if kbd.just_pressed(KeyCode::Down) {
+        step.z += WIND_STEP;
+    }
+    if step == Vec3::ZERO {
+        return;
+    }
+
+    wind.0 += step;
+    info!("Wind: ({:.1}, {:.1})", wind.0.x, wind.0.z);
+}
+
+// steer_nozzle - an update system that turns the emission axis while Ctrl and the arrow keys are
+// held: left / right swing it around, up / down tilt it away from or back towards vertical.
+// Home points it straight up again.  Only new particles are affected.  While Ctrl is held, an
+// arrow at every emitter shows the current direction.
+fn steer_nozzle(
+    mut configuration: ResMut<Configuration>,
+    time: Res<Time<Real>>,
+    kbd: Res<Input<KeyCode>>,
+    emitters: Query<&Transform, With<Emitter>>,
+    mut gizmos: Gizmos,
+) {
+    if kbd.just_pressed(KeyCode::Home) {
+        configuration.nozzle_tilt = 0.0;
+        configuration.nozzle_heading = 0.0;
+        configuration.emission_axis = Vec3::Y;
+        info!("Nozzle: straight up");
+        return;
+    }
+    if !kbd.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) {
+        return;
+    }
+
+    let turn = NOZZLE_TURN_SPEED * time.delta_seconds();
+    let mut heading = configuration.nozzle_heading;
+    let mut tilt = configuration.nozzle_tilt;
+    if kbd.pressed(KeyCode::Left) {
+        heading += turn;
+    }
+    if kbd.pressed(KeyCode::Right) {
+        heading -= turn;
+    }
+    if kbd.pressed(KeyCode::Up) {
+        tilt += turn;
+    }
+    if kbd.pressed(KeyCode::Down) {
+        tilt -= turn;
+    }
+    let tilt = tilt.clamp(0.0, std::f32::consts::FRAC_PI_2);
+    let heading = heading.rem_euclid(std::f32::consts::TAU);
+
+    if tilt != configuration.nozzle_tilt || heading != configuration.nozzle_heading {
+        configuration.nozzle_tilt = tilt;
+        configuration.nozzle_heading = heading;
+        configuration.emission_axis = Vec3::new(
+            tilt.sin() * heading.sin(),
+            tilt.cos(),
+            tilt.sin() * heading.cos(),
+        );
+    }
+
+    for transform in emitters.iter() {
+        draw_arrow(
+            &mut gizmos,
+            transform.translation,
+            configuration.emission_axis * 1.5,
+            Color::ORANGE,
+        );
+    }
+}
+
+// apply_wind - an update system that applies the wind force to every particle.
+fn apply_wind(wind: Res<Wind>, mut query: Query<&mut ExternalForce, With<ParticleMarker>>) {
+    for mut external_force in query.iter_mut() {
+        external_force.force = wind.0;
+    }
+}
+
+// toggle_attractor - an update system that switches the attractor on and off when 'T' is pressed.
+fn toggle_attractor(mut attractor: ResMut<Attractor>, kbd: Res<Input<KeyCode>>) {
+    if kbd.just_pressed(KeyCode::T) {
+        attractor.enabled = !attractor.enabled;
+        info!("Attractor {}", if attractor.enabled { "on" } else { "off" });
+    }
+}
+
+// control_panel - an update system that draws a panel with sliders for the main spawn and
+// physics settings.
+fn control_panel(
+    mut contexts: EguiContexts,
+    time: Res<Time>,
+    mut configuration: ResMut<Configuration>,
+    mut rapier_configuration: ResMut<RapierConfiguration>,
+    mut emitters: Query<&mut Emitter>,
+    mut over_ui: ResMut<PointerOverUi>,
+) {
+    let mut spawn_count = configuration.spawn_count;
+    let mut spawn_delta_ms = configuration.spawn_delta.as_millis() as u64;
+    let (mut min_radius, mut max_radius) = configuration.particle_radius_range;
+    let mut speed = configuration.speed_distribution.mean();
+    let mut expire_time = configuration.particle_expire_time;
+    let mut gravity = -rapier_configuration.gravity.y;
+    let mut show_fog = configuration.show_fog;
+    let mut fog_start = configuration.fog_start;
+    let mut fog_end = configuration.fog_end;
+
+    let ctx = contexts.ctx_mut();
+    let mut changed = false;
+    egui::Window::new("Controls").show(ctx, |ui| {
+        changed |= ui
+            .add(
+                egui::Slider::new(&mut spawn_count, 1..=MAX_SPAWN_COUNT)
+                    .logarithmic(true)
+                    .text("Spawn count"),
+            )
+            .changed();
+        changed |= ui
+            .add(
+                egui::Slider::new(&mut spawn_delta_ms, MIN_SPAWN_DELTA_MS..=MAX_SPAWN_DELTA_MS)
+                    .logarithmic(true)
+                    .text("Spawn delta (ms)"),
+            )
+            .changed();
+        let (radius_low, radius_high) = PANEL_RADIUS_RANGE;
+        changed |= ui
+            .add(egui::Slider::new(&mut min_radius, radius_low..=radius_high).text("Min radius"))
+            .changed();
+        changed |= ui
+            .add(egui::Slider::new(&mut max_radius, radius_low..=radius_high).text("Max radius"))
+            .changed();
+        let (speed_low, speed_high) = PANEL_SPEED_RANGE;
+        changed |= ui
+            .add(egui::Slider::new(&mut speed, speed_low..=speed_high).text("Initial velocity"))
+            .changed();
+        let (expire_low, expire_high) = PANEL_EXPIRE_TIME_RANGE;
+        changed |= ui
+            .add(
+                egui::Slider::new(&mut expire_time, expire_low..=expire_high)
+                    .text("Expire time (s)"),
+            )
+            .changed();
+        changed |= ui
+            .add(egui::Slider::new(&mut gravity, 0.0..=MAX_GRAVITY).text("Gravity"))
+            .changed();
+        changed |= ui.checkbox(&mut show_fog, "Fog").changed();
+        let (fog_low, fog_high) = PANEL_FOG_DISTANCE_RANGE;
+        changed |= ui
+            .add(egui::Slider::new(&mut fog_start, fog_low..=fog_high).text("Fog start"))
+            .changed();
+        changed |= ui
+            .add(egui::Slider::new(&mut fog_end, fog_low..=fog_high).text("Fog end"))
+            .changed();
+    });
+
+    // Keep the scene's mouse controls off the panel.
+    over_ui.0 = ctx.wants_pointer_input() || ctx.is_pointer_over_area();
+
+    if !changed {
+        return;
+    }
+
+    let previous = configuration.clone();
+    configuration.spawn_count = spawn_count;
+    configuration.spawn_delta = Duration::from_millis(spawn_delta_ms);
+    // Don't let the two ends of the radius range cross; the one being dragged pushes the other.
+    if min_radius != previous.particle_radius_range.0 {
+        max_radius = max_radius.max(min_radius);
+    } else {
+        min_radius = min_radius.min(max_radius);
+    }
+    configuration.particle_radius_range = (min_radius, max_radius);
+    if previous.speed_distribution.mean() > 0.0 {
+        configuration.speed_distribution = previous
+            .speed_distribution
+            .scaled(speed / previous.speed_distribution.mean());
+    }
+    if expire_time != previous.particle_expire_time {
+        configuration.set_particle_expire_time(expire_time);
+    }
+    // As with the radius range, the fog's start and end don't cross.
+    if fog_start != previous.fog_start {
+        fog_end = fog_end.max(fog_start);
+    } else {
+        fog_start = fog_start.min(fog_end);
+    }
+    configuration.show_fog = show_fog;
+    configuration.fog_start = fog_start;
+    configuration.fog_end = fog_end;
+    retune_emitters(
+        &previous,
+        &configuration,
+        &mut emitters,
+        time.elapsed_seconds(),
+    );
+
+    rapier_configuration.gravity.y = -gravity;
+}
+
+// retune_emitters - carries changes to the configuration's spawn count, spawn delta, spawn rate,
+// initial speed and expire time over to the emitters.  The emitters were set up from the configuration
+// with their own variations, so each change is applied proportionally to keep those differences.
+fn retune_emitters(
+    previous: &Configuration,
+    configuration: &Configuration,
+    emitters: &mut Query<&mut Emitter>,
+    now: f32,
+) {
+    // (A previous value of zero leaves nothing to scale.)
+    if configuration.spawn_count != previous.spawn_count && previous.spawn_count > 0 {
+        let factor = configuration.spawn_count as f32 / previous.spawn_count as f32;
+        for mut emitter in emitters.iter_mut() {
+            emitter.spawn_count = ((emitter.spawn_count as f32 * factor).round() as usize).max(1);
+        }
+    }
+    if configuration.spawn_delta != previous.spawn_delta {
+        let factor = configuration.spawn_delta.as_secs_f32() / previous.spawn_delta.as_secs_f32();
+        for mut emitter in emitters.iter_mut() {
+            scale_spawn_delta(&mut emitter, factor, now);
+        }
+    }
+    if configuration.spawn_rate != previous.spawn_rate && previous.spawn_rate > 0.0 {
+        let factor = configuration.spawn_rate / previous.spawn_rate;
+        for mut emitter in emitters.iter_mut() {
+            emitter.rate *= factor;
+        }
+    }
+    if configuration.speed_distribution != previous.speed_distribution
+        && previous.speed_distribution.mean() > 0.0
+    {
+        let factor = configuration.speed_distribution.mean() / previous.speed_distribution.mean();
+        for mut emitter in emitters.iter_mut() {
+            emitter.speed = emitter.speed.scaled(factor);
+        }
+    }
+    if configuration.particle_expire_time != previous.particle_expire_time
+        && previous.particle_expire_time > 0.0
+    {
+        let factor = configuration.particle_expire_time / previous.particle_expire_time;
+        for mut emitter in emitters.iter_mut() {
+            emitter.expire_time *= factor;
+        }
+    }
+}
+
+// run_emission_script - an update system that steps through the emission script's phases,
+// applying each one's settings (to the configuration and, through it, the emitters) as it starts.
+// After the last phase the script either starts over or holds, depending on 'looping'.
+fn run_emission_script(
+    mut script: ResMut<EmissionScript>,
+    time: Res<Time>,
+    mut configuration: ResMut<Configuration>,
+    mut emitters: Query<&mut Emitter>,
+) {
+    if !script.enabled || script.phases.is_empty() {
+        return;
+    }
+
+    // Work out which phase should be running; a long frame may skip right past short phases.
+    let now = time.elapsed_seconds();
+    let mut index = match script.current {
+        None => {
+            script.phase_start = now;
+            0
+        }
+        Some(current) => current,
+    };
+    // (At most one full pass per frame, so a script of zero-length phases can't spin forever.)
+    for _ in 0..script.phases.len() {
+        let duration = script.phases[index].duration;
+        if now - script.phase_start < duration {
+            break;
+        }
+        let next = if index + 1 < script.phases.len() {
+            index + 1
+        } else if script.looping {
+            0
+        } else {
+            // Hold the last phase.
+            break;
+        };
+        script.phase_start += duration;
+        index = next;
+    }
+    if script.current == Some(index) {
+        return;
+    }
+    script.current = Some(index);
+
+    // Apply the new phase.  Stopping just switches emission off, so the emitters' own rates
+    // survive to be scaled back up by the next phase.
+    let phase = script.phases[index].clone();
+    info!("Emission phase: {}", phase.name);
+    if phase.spawn_rate <= 0.0 || phase.spawn_count == 0 {
+        configuration.emitting = false;
+        return;
+    }
+    let previous = configuration.clone();
+    configuration.emitting = true;
+    configuration.spawn_rate = phase.spawn_rate;
+    configuration.spawn_count = phase.spawn_count;
+    if previous.speed_distribution.mean() > 0.0 {
+        configuration.speed_distribution = previous
+            .speed_distribution
+            .scaled(phase.speed / previous.speed_distribution.mean());
+    }
+    retune_emitters(&previous, &configuration, &mut emitters, now);
+}
+
+// toggle_emission_script - an update system that starts or stops the emission script when 'O'
+// is pressed.  Stopping leaves the current settings in place, with emission switched on;
+// starting begins again from the first phase.
+fn toggle_emission_script(
+    mut script: ResMut<EmissionScript>,
+    mut configuration: ResMut<Configuration>,
+    kbd: Res<Input<KeyCode>>,
+) {
+    if kbd.just_pressed(KeyCode::O) {
+        script.enabled = !script.enabled;
+        script.current = None;
+        if !script.enabled {
+            configuration.emitting = true;
+        }
+        info!(
+            "Emission script: {}",
+            if script.enabled { "on" } else { "off" }
+        );
+    }
+}
+
+// toggle_emitter_gizmos - an update system that shows or hides the emitter gizmos when F1 is
+// pressed.
+fn toggle_emitter_gizmos(mut configuration: ResMut<Configuration>, kbd: Res<Input<KeyCode>>) {
+    if kbd.just_pressed(KeyCode::F1) {
+        configuration.show_emitter_gizmos = !configuration.show_emitter_gizmos;
+        info!(
+            "Emitter gizmos: {}",
+            if configuration.show_emitter_gizmos {
+                "on"
+            } else {
+                "off"
+            }
+        );
+    }
+}
+
+// draw_emitter_gizmos - an update system that (when switched on) marks every emitter's origin
+// with a small sphere, outlines the region its particles spawn in and draws an arrow along the
+// middle of its emission cone.
+fn draw_emitter_gizmos(
+    configuration: Res<Configuration>,
+    emitters: Query<(&Transform, &Emitter)>,
+    mut gizmos: Gizmos,
+) {
+    if !configuration.show_emitter_gizmos {
+        return;
+    }
+
+    let region_color = Color::WHITE.with_a(0.4);
+    for (transform, emitter) in emitters.iter() {
+        let origin = transform.translation;
+        gizmos.sphere(origin, Quat::IDENTITY, 0.15, Color::YELLOW);
+
+        // Outline the spawn region, and find where the particles are launched from on average.
+        let center = match emitter.shape {
+            EmitterShape::Point => {
+                let max_offset = configuration.max_spawn_offset.min(GROUND_RADIUS);
+                let center = origin + Vec3::Y * configuration.spawn_height;
+                // Rectangles are drawn in the XY plane; lay this one flat.
+                gizmos.rect(
+                    center,
+                    Quat::from_rotation_x(std::f32::consts::FRAC_PI_2),
+                    Vec2::splat(max_offset * 2.0),
+                    region_color,
+                );
+                center
+            }
+            EmitterShape::Sphere { radius } => {
+                // Sphere emitters launch radially, so there's no single direction to show.
+                gizmos.sphere(origin, Quat::IDENTITY, radius, region_color);
+                continue;
+            }
+            // The mesh itself shows the spawn region, and launches follow its normals.
+            EmitterShape::Surface => continue,
+            EmitterShape::Ring { radius } | EmitterShape::Disc { radius } => {
+                gizmos.circle(origin, Vec3::Y, radius, region_color);
+                origin
+            }
+            EmitterShape::Box { half_extents } => {
+                gizmos.cuboid(
+                    Transform::from_translation(origin).with_scale(half_extents * 2.0),
+                    region_color,
+                );
+                origin
+            }
+        };
+
+        let direction = configuration.emission_axis.normalize();
+        draw_arrow(&mut gizmos, center, direction * 1.5, Color::YELLOW);
+    }
+}
+
+// draw_arrow - draws an arrow from 'start' along 'vector' with gizmos.
+fn draw_arrow(gizmos: &mut Gizmos, start: Vec3, vector: Vec3, color: Color) {
+    let tip = start + vector;
+    let back = vector.normalize() * 0.3;
+    let side = vector.any_orthonormal_vector() * 0.15;
+    gizmos.line(start, tip, color);
+    gizmos.line(tip, tip - back + side, color);
+    gizmos.line(tip, tip - back - side, color);
+}
+
+// place_attractor - an update system that moves the attractor to wherever the ground is
+// middle-clicked, and marks its position while it's on.
+fn place_attractor(
+    mut attractor: ResMut<Attractor>,
+    mouse: Res<Input<MouseButton>>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    cameras: Query<(&Camera, &GlobalTransform), With<Camera3d>>,
+    mut gizmos: Gizmos,
+) {
+    if mouse.just_pressed(MouseButton::Middle) {
+        // (there may be no single window or camera, e.g. while the window is closing)
+        if let (Ok(window), Ok((camera, camera_transform))) =
+            (windows.get_single(), cameras.get_single())
+        {
+            if let Some(hit) = cursor_ground_hit(window, camera, camera_transform) {
+                // Hover a little above the ground so particles can gather around it.
+                attractor.position = hit + Vec3::Y * 2.0;
+            }
+        }
+    }
+
+    if attractor.enabled {
+        gizmos.sphere(attractor.position, Quat::IDENTITY, 0.3, Color::CYAN);
+    }
+}
+
+// apply_attractor - an update system that pulls every particle towards the attractor.  Runs after
+// apply_wind, adding to the force it set.
+fn apply_attractor(
+    attractor: Res<Attractor>,
+    mut query: Query<(&Transform, &mut ExternalForce), With<ParticleMarker>>,
+) {
+    if !attractor.enabled {
+        return;
+    }
+
+    for (transform, mut external_force) in query.iter_mut() {
+        external_force.force += radial_force(
+            attractor.position,
+            transform.translation,
+            attractor.strength,
+            ATTRACTOR_MIN_DISTANCE,
+            ATTRACTOR_MAX_FORCE,
+        );
+    }
+}
+
+// radial_force - returns the inverse square force a point at 'center' exerts on something at
+// 'position'.  A positive 'strength' pulls towards the center, a negative one pushes away.  The
+// distance is clamped to 'min_distance' and the result to 'max_force' so it stays finite (and
+// the solver stable) close to the center.
+fn radial_force(
+    center: Vec3,
+    position: Vec3,
+    strength: f32,
+    min_distance: f32,
+    max_force: f32,
+) -> Vec3 {
+    let offset = center - position;
+
+    // normalize_or_zero copes with something sitting right on top of the center.
+    let distance = offset.length().max(min_distance);
+    let force = offset.normalize_or_zero() * strength / (distance * distance);
+    force.clamp_length_max(max_force)
+}
+
+// flip_attractor - an update system that switches the attractor between pulling and pushing
+// (a sustained repulsor) when 'Y' is pressed.
+fn flip_attractor(mut attractor: ResMut<Attractor>, kbd: Res<Input<KeyCode>>) {
+    if kbd.just_pressed(KeyCode::Y) {
+        attractor.strength = -attractor.strength;
+        info!(
+            "Attractor {}",
+            if attractor.strength < 0.0 {
+                "repels"
+            } else {
+                "attracts"
+            }
+        );
+    }
+}
+
+// explode_at_cursor - an update system that blasts particles away from the spot on the ground
+// under the cursor when 'X' is pressed.  The impulse falls off with the square of the distance.
+fn explode_at_cursor(
+    kbd: Res<Input<KeyCode>>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    cameras: Query<(&Camera, &GlobalTransform), With<Camera3d>>,
+    mut query: Query<(&Transform, &mut ExternalImpulse), With<ParticleMarker>>,
+) {
+    if !kbd.just_pressed(KeyCode::X) {
+        return;
+    }
+
+    // (there may be no single window or camera, e.g. while the window is closing)
+    let (Ok(window), Ok((camera, camera_transform))) = (windows.get_single(), cameras.get_single())
+    else {
+        return;
+    };
+    let Some(center) = cursor_ground_hit(window, camera, camera_transform) else {
+        return;
+    };
+
+    for (transform, mut external_impulse) in query.iter_mut() {
+        external_impulse.impulse += radial_force(
+            center,
+            transform.translation,
+            -EXPLOSION_STRENGTH,
+            EXPLOSION_MIN_DISTANCE,
+            EXPLOSION_MAX_IMPULSE,
+        );
+    }
+}
+
+// toggle_emission - an update system that switches all the emitters on or off when Enter is
+// pressed.  Particles that are already alive carry on as normal.
+fn toggle_emission(mut configuration: ResMut<Configuration>, kbd: Res<Input<KeyCode>>) {
+    if kbd.just_pressed(KeyCode::Return) {
+        configuration.emitting = !configuration.emitting;
+        info!(
+            "Emission {}",
+            if configuration.emitting { "on" } else { "off" }
+        );
+    }
+}
+
+// update_status_text - an update system that shows the states worth calling out on screen,
+// such as being paused or emission being switched off, followed by the latest notice (if it's
+// still current).  Notices time out on the real clock so they still go away while paused.
+#[allow(clippy::too_many_arguments)]
+fn update_status_text(
+    configuration: Res<Configuration>,
+    active_preset: Res<ActivePreset>,
+    paused: Res<Paused>,
+    adaptive_rate: Res<AdaptiveRate>,
+    time_scale: Res<TimeScale>,
+    time: Res<Time<Real>>,
+    notice: Res<StatusNotice>,
+    mut query: Query<&mut Text, With<StatusText>>,
+) {
+    let mut lines = Vec::new();
+    if let Some(name) = active_preset.0 {
+        lines.push(format!("Preset: {}", name));
+    }
+    if paused.0 {
+        lines.push("PAUSED".to_string());
+    }
+    if !configuration.emitting {
+        lines.push("EMISSION: OFF".to_string());
+    }
+    if time_scale.0 != 1.0 {
+        lines.push(format!("Time scale: {}x", time_scale.0));
+    }
+    if configuration.adaptive_rate {
+        lines.push(format!(
+            "Adaptive rate: {:.0}% (target {:.0} FPS)",
+            adaptive_rate.0 * 100.0,
+            configuration.target_fps
+        ));
+    }
+    if let Some(notice) = notice.current(time.elapsed_seconds()) {
+        lines.push(notice.to_string());
+    }
+
+    // Only touch the text when it changes, so the UI isn't laid out again every frame.
+    let value = lines.join("\n");
+    for mut text in &mut query {
+        if text.sections[0].value != value {
+            text.sections[0].value = value.clone();
+        }
+    }
+}
+
+// toggle_emission_mode - an update system that switches between batch, continuous and pulsed
+// emission when 'M' is pressed.
+fn toggle_emission_mode(mut configuration: ResMut<Configuration>, kbd: Res<Input<KeyCode>>) {
+    if kbd.just_pressed(KeyCode::M) {
+        configuration.emission_mode = match configuration.emission_mode {
+            EmissionMode::Batch => EmissionMode::Continuous,
+            EmissionMode::Continuous => EmissionMode::Pulsed,
+            EmissionMode::Pulsed => EmissionMode::Batch,
+        };
+        info!("Emission mode: {:?}", configuration.emission_mode);
+    }
+}
+
+// toggle_stagger_batches - an update system that switches between staggered and clumped batches
+// when 'J' is pressed.
+fn toggle_stagger_batches(mut configuration: ResMut<Configuration>, kbd: Res<Input<KeyCode>>) {
+    if kbd.just_pressed(KeyCode::J) {
+        configuration.stagger_batches = !configuration.stagger_batches;
+        info!(
+            "Staggered batches: {}",
+            if configuration.stagger_batches {
+                "on"
+            } else {
+                "off"
+            }
+        );
+    }
+}
+
+// toggle_adaptive_rate - an update system that switches the adaptive spawn rate on or off when
+// 'L' is pressed.  It starts again from the full rate each time it's switched on.
+fn toggle_adaptive_rate(
+    mut configuration: ResMut<Configuration>,
+    mut adaptive_rate: ResMut<AdaptiveRate>,
+    kbd: Res<Input<KeyCode>>,
+) {
+    if kbd.just_pressed(KeyCode::L) {
+        configuration.adaptive_rate = !configuration.adaptive_rate;
+        adaptive_rate.0 = configuration.adaptive_rate_range.1;
+        info!(
+            "Adaptive spawn rate: {}",
+            if configuration.adaptive_rate {
+                "on"
+            } else {
+                "off"
+            }
+        );
+    }
+}
+
+// toggle_emission_pattern - an update system that switches between random and spiral launch
+// directions when 'P' is pressed.
+fn toggle_emission_pattern(mut configuration: ResMut<Configuration>, kbd: Res<Input<KeyCode>>) {
+    if kbd.just_pressed(KeyCode::P) {
+        configuration.emission_pattern = match configuration.emission_pattern {
+            EmissionPattern::Random => EmissionPattern::Spiral,
+            EmissionPattern::Spiral => EmissionPattern::Random,
+        };
+        info!("Emission pattern: {:?}", configuration.emission_pattern);
+    }
+}
+
+// toggle_spawn_preset - an update system that switches the emitters between fountain and rain
+// when 'N' is pressed.  Particles already in flight carry on as they were.
+fn toggle_spawn_preset(mut configuration: ResMut<Configuration>, kbd: Res<Input<KeyCode>>) {
+    if kbd.just_pressed(KeyCode::N) {
+        configuration.spawn_preset = match configuration.spawn_preset {
+            SpawnPreset::Fountain => SpawnPreset::Rain,
+            SpawnPreset::Rain => SpawnPreset::Fountain,
+        };
+        info!("Spawn preset: {:?}", configuration.spawn_preset);
+    }
+}
+
+// cycle_emission_shape - an update system that switches to the next distribution of launch
+// directions when '\' is pressed.
+fn cycle_emission_shape(mut configuration: ResMut<Configuration>, kbd: Res<Input<KeyCode>>) {
+    if kbd.just_pressed(KeyCode::Backslash) {
+        configuration.emission_shape = match configuration.emission_shape {
+            EmissionShape::Point => EmissionShape::Cone,
+            EmissionShape::Cone => EmissionShape::Disk { radius: 0.25 },
+            EmissionShape::Disk { .. } => EmissionShape::Sphere,
+            EmissionShape::Sphere => EmissionShape::Legacy,
+            EmissionShape::Legacy => EmissionShape::Point,
+        };
+        info!("Emission shape: {:?}", configuration.emission_shape);
+    }
+}
+
+// evict_oldest_particles - an update system that, with the EvictOldest cap policy, makes the
+// particles closest to expiring expire now whenever there are more than max_particles, so
+// despawn_particles (which runs right after) brings the count back down in the same frame.
+fn evict_oldest_particles(
+    configuration: Res<Configuration>,
+    time: Res<Time>,
+    mut particles: Query<(Entity, &mut ExpireTime, &mut RetireCause), With<ParticleMarker>>,
+) {
+    if configuration.cap_policy != CapPolicy::EvictOldest {
+        return;
+    }
+    let live_particles = particles.iter().count();
+    let excess = live_particles.saturating_sub(configuration.max_particles);
+    if excess == 0 {
+        return;
+    }
+
+    // Only the 'excess' earliest expire times are needed, not a full sort.
+    let mut by_expire_time: Vec<(f32, Entity)> = particles
+        .iter()
+        .map(|(entity, expire_time, _)| (expire_time.0, entity))
+        .collect();
+    if excess < by_expire_time.len() {
+        by_expire_time.select_nth_unstable_by(excess, |a, b| a.0.total_cmp(&b.0));
+    }
+
+    let now = time.elapsed_seconds();
+    for &(_, entity) in &by_expire_time[..excess] {
+        if let Ok((_, mut expire_time, mut cause)) = particles.get_mut(entity) {
+            retire_early(&mut expire_time, &mut cause, now, DespawnCause::Evicted);
+        }
+    }
+}
+
+// retire_early - brings a particle's expire time forward to 'at' (recording 'cause' as the
+// reason), unless it's due to expire by then anyway.  Returns whether it did.
+fn retire_early(
+    expire_time: &mut ExpireTime,
+    retire_cause: &mut RetireCause,
+    at: f32,
+    cause: DespawnCause,
+) -> bool {
+    if at >= expire_time.0 {
+        return false;
+    }
+    expire_time.0 = at;
+    retire_cause.0 = cause;
+    true
+}
+
+// despawn_particles - an update system that will retire any particles that have outlived
+// their expire-time, along with the other half of any dumbbell among them.  Retired particles go
+// back to the pool until it's full, after which they're despawned.  A ParticleDespawned event
+// is sent for each.
+fn despawn_particles(
+    mut commands: Commands,
+    time: Res<Time>,
+    configuration: Res<Configuration>,
+    query: Query<
+        (
+            Entity,
+            &ExpireTime,
+            &RetireCause,
+            &Transform,
+            Option<&PairPartner>,
+        ),
+        With<ParticleMarker>,
+    >,
+    pool: Query<(), With<PooledParticle>>,
+    mut despawned: EventWriter<ParticleDespawned>,
+) {
+    // Determine if it's time to despawn particles...  The other half of a dumbbell goes for the
+    // same reason as the half that expired.
+    let now = time.elapsed_seconds();
+    let mut retiring = Vec::new();
+    for (entity, expire_time, cause, _, partner) in query.iter() {
+        if now >= expire_time.0 {
+            retiring.push((entity, cause.0));
+            if let Some(partner) = partner.filter(|partner| query.contains(partner.0)) {
+                retiring.push((partner.0, cause.0));
+            }
+        }
+    }
+    // (both halves of a dumbbell may have expired on their own)
+    retiring.sort_unstable_by_key(|&(entity, _)| entity);
+    retiring.dedup_by_key(|&mut (entity, _)| entity);
+
+    // ...if so, do it.
+    let mut pooled = pool.iter().count();
+    for (entity, cause) in retiring {
+        if let Ok((_, _, _, transform, _)) = query.get(entity) {
+            despawned.send(ParticleDespawned {
+                entity,
+                position: transform.translation,
+                cause,
+            });
+        }
+        if pooled < configuration.pool_size {
+            // Hide the particle, take it out of the physics simulation and drop its trail and
+            // any joint.
+            commands
+                .entity(entity)
+                .remove::<(ParticleMarker, PairPartner, ImpulseJoint)>()
+                .insert((
+                    PooledParticle,
+                    RigidBodyDisabled,
+                    ColliderDisabled,
+                    Visibility::Hidden,
+                    TrailPoints::default(),
+                ));
+            pooled += 1;
+        } else {
+            commands.entity(entity).despawn()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::time::{TimePlugin, TimeUpdateStrategy};
+    use bevy::utils::HashSet;
+
+    // How far the clock moves on with each update of a test app.
+    const FRAME: Duration = Duration::from_millis(100);
+
+    // test_configuration - returns the built-in configuration (its meshes and materials are
+    // thrown away).
+    fn test_configuration() -> Configuration {
+        Configuration::new(&mut Assets::default(), &mut Assets::default())
+    }
+
+    // test_app - returns an app that runs the burst spawner ('B') and despawn_particles on a
+    // manually advanced clock, with the built-in configuration changed by 'configure'.
+    fn test_app(configure: impl FnOnce(&mut Configuration)) -> App {
+        let mut meshes = Assets::<Mesh>::default();
+        let mut materials = Assets::<StandardMaterial>::default();
+        let mut configuration = Configuration::new(&mut meshes, &mut materials);
+        configure(&mut configuration);
+
+        let mut app = App::new();
+        app.add_plugins(TimePlugin)
+            .insert_resource(TimeUpdateStrategy::ManualDuration(FRAME))
+            .insert_resource(meshes)
+            .insert_resource(materials)
+            .insert_resource(configuration)
+            .insert_resource(ParticleRng::new(Some(0)))
+            .init_resource::<Input<KeyCode>>()
+            .add_event::<ParticleSpawned>()
+            .add_event::<ParticleDespawned>()
+            .add_systems(
+                Update,
+                (burst_particles, apply_deferred, despawn_particles).chain(),
+            );
+        app
+    }
+
+    // burst - runs one update of a test app with 'B' just pressed.
+    fn burst(app: &mut App) {
+        app.world.resource_mut::<Input<KeyCode>>().press(KeyCode::B);
+        app.update();
+        let mut kbd = app.world.resource_mut::<Input<KeyCode>>();
+        kbd.release(KeyCode::B);
+        kbd.clear();
+    }
+
+    // entities - returns every entity matching the filter 'F'.
+    fn entities<F: bevy::ecs::query::ReadOnlyWorldQuery>(app: &mut App) -> HashSet<Entity> {
+        let mut query = app.world.query_filtered::<Entity, F>();
+        query.iter(&app.world).collect()
+    }
+
+    #[test]
+    fn expired_particles_are_reused_from_the_pool() {
+        let mut app = test_app(|configuration| {
+            configuration.burst_size = 10;
+            configuration.pool_size = 6;
+            configuration.particle_expire_time = 1.0;
+            configuration.lifetime_range = (1.0, 1.0);
+        });
+
+        for round in 0..3 {
+            let pooled = entities::<With<PooledParticle>>(&mut app);
+            burst(&mut app);
+            let live = entities::<With<ParticleMarker>>(&mut app);
+            assert_eq!(live.len(), 10);
+            // A burst takes every pooled entity before allocating any new ones.
+            assert!(pooled.is_subset(&live), "round {}", round);
+            assert!(entities::<With<PooledParticle>>(&mut app).is_empty());
+
+            // Once the burst expires, the pool holds on to as many of its entities as it may
+            // and the rest are despawned.
+            for _ in 0..20 {
+                app.update();
+                assert!(entities::<With<PooledParticle>>(&mut app).len() <= 6);
+            }
+            assert!(entities::<With<ParticleMarker>>(&mut app).is_empty());
+            let pooled = entities::<With<PooledParticle>>(&mut app);
+            assert_eq!(pooled.len(), 6);
+            assert!(pooled.is_subset(&live));
+        }
+    }
+
+    #[test]
+    fn collider_matches_the_scaled_mesh() {
+        let mut app = test_app(|configuration| configuration.burst_size = 100);
+        burst(&mut app);
+
+        let (min_radius, max_radius) = app.world.resource::<Configuration>().particle_radius_range;
+        let mut query = app
+            .world
+            .query_filtered::<(&Transform, &ParticleScale, &Collider), With<ParticleMarker>>();
+        for (transform, scale, collider) in query.iter(&app.world) {
+            // The mesh is PARTICLE_RADIUS across before scaling, and Rapier scales the collider
+            // along with the transform.
+            let mesh_radius = scale.0 * PARTICLE_RADIUS;
+            let collider_radius = collider.as_ball().unwrap().radius() * transform.scale.x;
+            assert!((collider_radius - mesh_radius).abs() < 1e-5);
+            assert!(mesh_radius >= min_radius - 1e-5 && mesh_radius <= max_radius + 1e-5);
+        }
+    }
+
+    #[test]
+    fn cone_directions_stay_within_the_half_angle() {
+        let mut rng = rngs::StdRng::seed_from_u64(0);
+        let axes = [Vec3::Y, Vec3::new(1.0, 2.0, -0.5), Vec3::NEG_X];
+        for spread_angle in [0.1, 0.5, 1.0, 2.0, std::f32::consts::PI] {
+            let half_angle = spread_angle / 2.0;
+            for axis in axes {
+                for _ in 0..1000 {
+                    let direction = sample_cone(axis, spread_angle, &mut rng);
+                    assert!((direction.length() - 1.0).abs() < 1e-5);
+                    assert!(direction.angle_between(axis) <= half_angle + 1e-3);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn negative_strength_pushes_away_from_the_center() {
+        let center = Vec3::new(1.0, 2.0, 3.0);
+        let offset = Vec3::new(2.0, 0.0, -1.0);
+        let force = radial_force(
+            center,
+            center + offset,
+            -EXPLOSION_STRENGTH,
+            EXPLOSION_MIN_DISTANCE,
+            EXPLOSION_MAX_IMPULSE,
+        );
+        assert!(force.normalize().dot(offset.normalize()) > 0.9999);
+        // Inverse square: a strength of 5 at a distance of sqrt(5) gives 1.
+        assert!((force.length() - EXPLOSION_STRENGTH / offset.length_squared()).abs() < 1e-5);
+
+        // Close to the center the push is capped, and right on it there's none at all.
+        let close = radial_force(
+            center,
+            center + Vec3::X * 0.01,
+            -EXPLOSION_STRENGTH * 100.0,
+            EXPLOSION_MIN_DISTANCE,
+            EXPLOSION_MAX_IMPULSE,
+        );
+        assert!(close.x > 0.0);
+        assert!(close.length() <= EXPLOSION_MAX_IMPULSE + 1e-4);
+        let on_top = radial_force(
+            center,
+            center,
+            -EXPLOSION_STRENGTH,
+            EXPLOSION_MIN_DISTANCE,
+            EXPLOSION_MAX_IMPULSE,
+        );
+        assert_eq!(on_top, Vec3::ZERO);
+    }
+
+    #[test]
+    fn particles_get_the_configured_friction() {
+        let mut app = test_app(|configuration| {
+            configuration.burst_size = 10;
+            configuration.particle_expire_time = 1.0;
+            configuration.lifetime_range = (1.0, 1.0);
+        });
+
+        // Check freshly spawned particles, then (with a new setting) recycled ones.
+        for friction in [0.8, 1.5] {
+            app.world.resource_mut::<Configuration>().friction = friction;
+            burst(&mut app);
+            let mut query = app
+                .world
+                .query_filtered::<&Friction, With<ParticleMarker>>();
+            assert_eq!(query.iter(&app.world).count(), 10);
+            for particle_friction in query.iter(&app.world) {
+                assert_eq!(particle_friction.coefficient, friction);
+                assert_eq!(
+                    particle_friction.combine_rule,
+                    CoefficientCombineRule::Average
+                );
+            }
+            for _ in 0..20 {
+                app.update();
+            }
+        }
+    }
+
+    #[test]
+    fn example_config_file_parses() {
+        let config_file = ron::from_str::<ConfigFile>(include_str!("../config.ron")).unwrap();
+        // The example spells out the defaults.
+        assert_eq!(config_file, ConfigFile::default());
+    }
+
+    #[test]
+    fn emitter_bundle_defaults_match_the_constants() {
+        let bundle = EmitterBundle::default();
+        let emitter = &bundle.emitter;
+        assert_eq!(
+            emitter.spawn_delta,
+            Duration::from_millis(PARTICLE_RESPAWN_TIME_MS)
+        );
+        assert_eq!(emitter.spawn_count, SPAWN_COUNT);
+        assert_eq!(emitter.speed, SpeedDistribution::Constant(INITIAL_VELOCITY));
+        assert_eq!(emitter.expire_time, PARTICLE_EXPIRE_TIME_SECS);
+        assert_eq!(emitter.rate, SPAWN_RATE);
+        assert_eq!(emitter.shape, EmitterShape::Point);
+        assert_eq!(bundle.transform.local, Transform::IDENTITY);
+
+        // new() is the same thing.
+        let new = EmitterBundle::new();
+        assert_eq!(new.emitter.spawn_count, emitter.spawn_count);
+        assert_eq!(new.emitter.speed, emitter.speed);
+        assert_eq!(new.emitter.rate, emitter.rate);
+    }
+
+    #[test]
+    fn emitter_bundle_setters_set_their_fields() {
+        let default = EmitterBundle::default();
+        assert_eq!(EmitterBundle::new().rate(200.0).emitter.rate, 200.0);
+        assert_eq!(EmitterBundle::new().count(7).emitter.spawn_count, 7);
+        assert_eq!(
+            EmitterBundle::new()
+                .shape(EmitterShape::Disc { radius: 2.0 })
+                .emitter
+                .shape,
+            EmitterShape::Disc { radius: 2.0 }
+        );
+        assert_eq!(
+            EmitterBundle::new().speed(3.0).emitter.speed,
+            SpeedDistribution::Constant(3.0)
+        );
+        assert_eq!(
+            EmitterBundle::new()
+                .lifetime(Duration::from_secs(8))
+                .emitter
+                .expire_time,
+            8.0
+        );
+        assert_eq!(
+            EmitterBundle::new()
+                .at(Vec3::new(0.0, 5.0, 0.0))
+                .transform
+                .local,
+            Transform::from_xyz(0.0, 5.0, 0.0)
+        );
+
+        // Chained, each setter leaves the others' fields alone.
+        let bundle = EmitterBundle::new()
+            .rate(200.0)
+            .shape(EmitterShape::Disc { radius: 2.0 })
+            .speed(3.0)
+            .lifetime(Duration::from_secs(8))
+            .at(Vec3::new(0.0, 5.0, 0.0));
+        assert_eq!(bundle.emitter.rate, 200.0);
+        assert_eq!(bundle.emitter.shape, EmitterShape::Disc { radius: 2.0 });
+        assert_eq!(bundle.emitter.speed.mean(), 3.0);
+        assert_eq!(bundle.emitter.expire_time, 8.0);
+        assert_eq!(bundle.transform.local.translation, Vec3::new(0.0, 5.0, 0.0));
+        assert_eq!(bundle.emitter.spawn_count, default.emitter.spawn_count);
+        assert_eq!(bundle.emitter.spawn_delta, default.emitter.spawn_delta);
+    }
+
+    #[test]
+    fn cone_directions_are_evenly_spread() {
+        const SAMPLES: usize = 10_000;
+        const AZIMUTH_BUCKETS: usize = 8;
+        const POLAR_BUCKETS: usize = 4;
+        let mut configuration = test_configuration();
+        configuration.emission_shape = EmissionShape::Cone;
+        configuration.spread_angle = 1.0;
+        let cos_half_angle = (configuration.spread_angle / 2.0).cos();
+        let mut rng = rngs::StdRng::seed_from_u64(0);
+
+        // Bucket the directions by azimuth around the (vertical) axis, and by cos(theta), which
+        // is evenly spread when the directions are evenly spread over the spherical cap.
+        let mut azimuths = [0; AZIMUTH_BUCKETS];
+        let mut polars = [0; POLAR_BUCKETS];
+        for _ in 0..SAMPLES {
+            let direction = sample_direction(&configuration, &mut rng);
+            let azimuth = direction.z.atan2(direction.x) + std::f32::consts::PI;
+            let bucket = (azimuth / std::f32::consts::TAU * AZIMUTH_BUCKETS as f32) as usize;
+            azimuths[bucket.min(AZIMUTH_BUCKETS - 1)] += 1;
+            let height = (direction.y - cos_half_angle) / (1.0 - cos_half_angle);
+            let bucket = (height * POLAR_BUCKETS as f32) as usize;
+            polars[bucket.min(POLAR_BUCKETS - 1)] += 1;
+        }
+
+        // Each bucket should be within 10% of its share (several standard deviations).
+        for count in azimuths {
+            let expected = SAMPLES / AZIMUTH_BUCKETS;
+            assert!(count.abs_diff(expected) < expected / 10, "{:?}", azimuths);
+        }
+        for count in polars {
+            let expected = SAMPLES / POLAR_BUCKETS;
+            assert!(count.abs_diff(expected) < expected / 10, "{:?}", polars);
+        }
+    }
+
+    #[test]
+    fn legacy_directions_match_the_original_jitter() {
+        let mut configuration = test_configuration();
+        configuration.emission_shape = EmissionShape::Legacy;
+        let mut rng = rngs::StdRng::seed_from_u64(0);
+        for _ in 0..1000 {
+            let direction = sample_direction(&configuration, &mut rng);
+            assert!((direction.length() - 1.0).abs() < 1e-5);
+            assert!(direction.y > 0.0);
+            assert!((direction.x / direction.y).abs() <= LEGACY_JITTER + 1e-5);
+            assert!((direction.z / direction.y).abs() <= LEGACY_JITTER + 1e-5);
+        }
+    }
+
+    #[test]
+    fn particles_do_not_expire_while_paused() {
+        let mut app = test_app(|configuration| {
+            configuration.burst_size = 10;
+            configuration.particle_expire_time = 1.0;
+            configuration.lifetime_range = (1.0, 1.0);
+        });
+        burst(&mut app);
+        for _ in 0..5 {
+            app.update();
+        }
+        assert_eq!(entities::<With<ParticleMarker>>(&mut app).len(), 10);
+
+        // Well past the expire time on the real clock, the particles are still there.
+        app.world.resource_mut::<Time<Virtual>>().pause();
+        let paused_at = app.world.resource::<Time>().elapsed_seconds();
+        for _ in 0..30 {
+            app.update();
+        }
+        assert_eq!(app.world.resource::<Time>().elapsed_seconds(), paused_at);
+        assert_eq!(entities::<With<ParticleMarker>>(&mut app).len(), 10);
+
+        // Once resumed, they expire after the rest of their lifetime.
+        app.world.resource_mut::<Time<Virtual>>().unpause();
+        let mut updates = 0;
+        while !entities::<With<ParticleMarker>>(&mut app).is_empty() {
+            app.update();
+            updates += 1;
+            assert!(updates <= 10);
+        }
+        assert!(app.world.resource::<Time>().elapsed_seconds() >= 1.0);
+    }
+
+    #[test]
+    fn zero_spread_launches_along_the_axis() {
+        let mut configuration = test_configuration();
+        configuration.emission_shape = EmissionShape::Cone;
+        configuration.spread_angle = 0.0;
+        let mut rng = rngs::StdRng::seed_from_u64(0);
+        for _ in 0..100 {
+            assert_eq!(sample_direction(&configuration, &mut rng), Vec3::Y);
+        }
+
+        // A tilted axis works the same way (up to rounding in the rotation).
+        let axis = Vec3::new(1.0, 2.0, 0.5).normalize();
+        for _ in 0..100 {
+            let direction = sample_cone(axis, 0.0, &mut rng);
+            assert!(direction.abs_diff_eq(axis, 1e-6));
+        }
+    }
+
+    #[test]
+    fn collision_groups_follow_the_particle_collisions_flag() {
+        // Whether two colliders' groups let them touch (Rapier's rule).
+        fn interact(a: CollisionGroups, b: CollisionGroups) -> bool {
+            a.memberships.intersects(b.filters) && b.memberships.intersects(a.filters)
+        }
+        // (the ground and the other scenery keep the default groups)
+        let scenery = CollisionGroups::default();
+        let mut configuration = test_configuration();
+
+        configuration.particle_collisions = true;
+        let groups = particle_collision_groups(&configuration);
+        assert_eq!(groups.memberships, PARTICLE_GROUP);
+        assert_eq!(groups.filters, Group::ALL);
+        assert!(interact(groups, groups));
+        assert!(interact(groups, scenery));
+
+        configuration.particle_collisions = false;
+        let groups = particle_collision_groups(&configuration);
+        assert_eq!(groups.memberships, PARTICLE_GROUP);
+        assert_eq!(groups.filters, Group::ALL - PARTICLE_GROUP);
+        assert!(!interact(groups, groups));
+        assert!(interact(groups, scenery));
+    }
+}
@@ -0,0 +1,75 @@
+use bevy::prelude::*;
+
+use crate::ParticleMarker;
+
+/// Marker to find the text entity so we can update it
+#[derive(Component)]
+pub struct ParticleCountText;
+
+pub fn setup_particle_counter(mut commands: Commands) {
+    // create our UI root node
+    // this is the wrapper/container for the text
+    let root = commands
+        .spawn(NodeBundle {
+            // give it a dark background for readability
+            background_color: BackgroundColor(Color::BLACK.with_a(0.5)),
+            // make it "always on top" by setting the Z index to maximum
+            // we want it to be displayed over all other UI
+            z_index: ZIndex::Global(i32::MAX),
+            style: Style {
+                position_type: PositionType::Absolute,
+                // position it at the bottom-left corner
+                // 1% away from the bottom window edge
+                left: Val::Percent(1.),
+                bottom: Val::Percent(1.),
+                // set top/right to Auto, so it can be
+                // automatically sized depending on the text
+                top: Val::Auto,
+                right: Val::Auto,
+                // give it some padding for readability
+                padding: UiRect::all(Val::Px(4.0)),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .id();
+    // create our text
+    let text_count = commands
+        .spawn((
+            ParticleCountText,
+            TextBundle {
+                // use two sections, so it is easy to update just the number
+                text: Text::from_sections([
+                    TextSection {
+                        value: "Particles: ".into(),
+                        style: TextStyle {
+                            font_size: 16.0,
+                            color: Color::WHITE,
+                            ..default()
+                        },
+                    },
+                    TextSection {
+                        value: "0".into(),
+                        style: TextStyle {
+                            font_size: 16.0,
+                            color: Color::WHITE,
+                            ..default()
+                        },
+                    },
+                ]),
+                ..Default::default()
+            },
+        ))
+        .id();
+    commands.entity(root).push_children(&[text_count]);
+}
+
+pub fn particle_count_update_system(
+    particles: Query<(), With<ParticleMarker>>,
+    mut query: Query<&mut Text, With<ParticleCountText>>,
+) {
+    let count = particles.iter().count();
+    for mut text in &mut query {
+        text.sections[1].value = count.to_string();
+    }
+}
@@ -0,0 +1,189 @@
+use bevy::prelude::*;
+
+use crate::{
+    retune_emitters, Configuration, EmissionMode, EmissionPattern, EmissionShape, Emitter,
+    PairJoint, ParticleShape, SpawnPreset, SpeedDistribution, INITIAL_VELOCITY,
+    INITIAL_VELOCITY_STD_DEV, LIFETIME_RANGE, MAX_ANGULAR_SPEED, MAX_PARTICLE_RADIUS,
+    MAX_SPAWN_OFFSET, MIN_PARTICLE_RADIUS, PAIR_PROBABILITY, PARTICLE_EXPIRE_TIME_SECS,
+    PARTICLE_RESPAWN_TIME_MS, PULSE_AMPLITUDE, PULSE_PERIOD_SECS, SPAWN_COUNT, SPAWN_HEIGHT,
+    SPAWN_RATE, SPIRAL_SPEED, SPIRAL_STEP, SPREAD_ANGLE,
+};
+use std::time::Duration;
+
+/// A named set of spawn settings that can be switched to with a number key
+pub struct Preset {
+    pub name: &'static str,
+    /// Whether the emitters make a fountain or rain
+    pub spawn_preset: SpawnPreset,
+    /// Distribution of the launch speed
+    pub speed: SpeedDistribution,
+    /// Full apex angle (in radians) of the emission cone
+    pub spread_angle: f32,
+    pub emission_shape: EmissionShape,
+    pub emission_pattern: EmissionPattern,
+    /// Radians the spiral pattern turns between particles, and per second on its own
+    pub spiral_step: f32,
+    pub spiral_speed: f32,
+    pub emission_mode: EmissionMode,
+    /// Particles per second in continuous mode
+    pub spawn_rate: f32,
+    /// How far the rate swings, and seconds between waves, in pulsed mode
+    pub pulse_amplitude: f32,
+    pub pulse_period: f32,
+    /// Particles per batch, and time between batches, in batch mode
+    pub spawn_count: usize,
+    pub spawn_delta: Duration,
+    /// Height above the emitter, and max offset across it, of new particles
+    pub spawn_height: f32,
+    pub max_spawn_offset: f32,
+    pub particle_shape: ParticleShape,
+    pub particle_radius_range: (f32, f32),
+    pub max_angular_speed: f32,
+    /// Seconds each particle lives, and the range its lifetime is drawn from
+    pub expire_time: f32,
+    pub lifetime_range: (f32, f32),
+    /// Chance that a particle comes as a dumbbell, and how its halves are joined
+    pub pair_probability: f32,
+    pub pair_joint: PairJoint,
+}
+
+/// The first preset: the spawn settings Configuration::new starts with.  The others only list
+/// what they change.
+const GENTLE_FOUNTAIN: Preset = Preset {
+    name: "Gentle fountain",
+    spawn_preset: SpawnPreset::Fountain,
+    speed: SpeedDistribution::Constant(INITIAL_VELOCITY),
+    spread_angle: SPREAD_ANGLE,
+    emission_shape: EmissionShape::Cone,
+    emission_pattern: EmissionPattern::Random,
+    spiral_step: SPIRAL_STEP,
+    spiral_speed: SPIRAL_SPEED,
+    emission_mode: EmissionMode::Continuous,
+    spawn_rate: SPAWN_RATE,
+    pulse_amplitude: PULSE_AMPLITUDE,
+    pulse_period: PULSE_PERIOD_SECS,
+    spawn_count: SPAWN_COUNT,
+    spawn_delta: Duration::from_millis(PARTICLE_RESPAWN_TIME_MS),
+    spawn_height: SPAWN_HEIGHT,
+    max_spawn_offset: MAX_SPAWN_OFFSET,
+    particle_shape: ParticleShape::Sphere,
+    particle_radius_range: (MIN_PARTICLE_RADIUS, MAX_PARTICLE_RADIUS),
+    max_angular_speed: MAX_ANGULAR_SPEED,
+    expire_time: PARTICLE_EXPIRE_TIME_SECS,
+    lifetime_range: LIFETIME_RANGE,
+    pair_probability: PAIR_PROBABILITY,
+    pair_joint: PairJoint::Spherical,
+};
+
+/// The built-in presets; the first one is selected with 1, the second with 2, and so on
+pub const PRESETS: &[Preset] = &[
+    GENTLE_FOUNTAIN,
+    Preset {
+        name: "Geyser",
+        speed: SpeedDistribution::Normal {
+            mean: INITIAL_VELOCITY * 3.0,
+            std_dev: INITIAL_VELOCITY_STD_DEV,
+        },
+        spread_angle: 0.15,
+        ..GENTLE_FOUNTAIN
+    },
+    Preset {
+        name: "Rain",
+        spawn_preset: SpawnPreset::Rain,
+        ..GENTLE_FOUNTAIN
+    },
+    Preset {
+        name: "Ring fountain",
+        speed: SpeedDistribution::Constant(INITIAL_VELOCITY * 1.5),
+        spread_angle: 1.2,
+        emission_pattern: EmissionPattern::Spiral,
+        ..GENTLE_FOUNTAIN
+    },
+    Preset {
+        name: "Chaos",
+        speed: SpeedDistribution::Normal {
+            mean: INITIAL_VELOCITY * 1.5,
+            std_dev: INITIAL_VELOCITY,
+        },
+        spread_angle: std::f32::consts::PI,
+        spawn_rate: SPAWN_RATE * 3.0,
+        spawn_count: SPAWN_COUNT * 3,
+        particle_radius_range: (0.05, 0.5),
+        ..GENTLE_FOUNTAIN
+    },
+];
+
+/// The keys that select the presets, in order
+const PRESET_KEYS: [KeyCode; 9] = [
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+];
+
+/// The preset picked last, if any (settings may have been changed since)
+#[derive(Resource, Default)]
+pub struct ActivePreset(pub Option<&'static str>);
+
+impl Preset {
+    /// Copies the preset's settings into the configuration
+    fn apply(&self, configuration: &mut Configuration) {
+        configuration.spawn_preset = self.spawn_preset;
+        configuration.speed_distribution = self.speed;
+        configuration.spread_angle = self.spread_angle;
+        configuration.emission_shape = self.emission_shape;
+        configuration.emission_pattern = self.emission_pattern;
+        configuration.spiral_step = self.spiral_step;
+        configuration.spiral_speed = self.spiral_speed;
+        configuration.emission_mode = self.emission_mode;
+        configuration.spawn_rate = self.spawn_rate;
+        configuration.pulse_amplitude = self.pulse_amplitude;
+        configuration.pulse_period = self.pulse_period;
+        configuration.spawn_count = self.spawn_count;
+        configuration.spawn_delta = self.spawn_delta;
+        configuration.spawn_height = self.spawn_height;
+        configuration.max_spawn_offset = self.max_spawn_offset;
+        configuration.particle_shape = self.particle_shape;
+        configuration.particle_radius_range = self.particle_radius_range;
+        configuration.max_angular_speed = self.max_angular_speed;
+        configuration.particle_expire_time = self.expire_time;
+        configuration.lifetime_range = self.lifetime_range;
+        configuration.pair_probability = self.pair_probability;
+        configuration.pair_joint = self.pair_joint;
+    }
+}
+
+/// Switch to a preset when its number key is pressed.  Particles that are already alive carry
+/// on as they were.
+pub fn select_preset(
+    kbd: Res<Input<KeyCode>>,
+    time: Res<Time>,
+    mut configuration: ResMut<Configuration>,
+    mut emitters: Query<&mut Emitter>,
+    mut active: ResMut<ActivePreset>,
+) {
+    let Some(preset) = PRESET_KEYS
+        .iter()
+        .zip(PRESETS)
+        .find_map(|(key, preset)| kbd.just_pressed(*key).then_some(preset))
+    else {
+        return;
+    };
+
+    // carry the changes over to the emitters, which keep their own variations on the settings
+    let previous = configuration.clone();
+    preset.apply(&mut configuration);
+    retune_emitters(
+        &previous,
+        &configuration,
+        &mut emitters,
+        time.elapsed_seconds(),
+    );
+    active.0 = Some(preset.name);
+    info!("Preset: {}", preset.name);
+}
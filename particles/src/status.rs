@@ -0,0 +1,75 @@
+use bevy::prelude::*;
+
+/// How long (in seconds) a notice stays on screen
+const NOTICE_SECS: f32 = 2.0;
+
+/// A short message (like a setting that just changed) shown on the status line for a moment
+#[derive(Resource, Default)]
+pub struct StatusNotice {
+    text: String,
+    /// Elapsed time (in seconds) at which the notice disappears
+    until: f32,
+}
+
+impl StatusNotice {
+    /// Shows `text` for the next few seconds, replacing any earlier notice
+    pub fn show(&mut self, text: impl Into<String>, now: f32) {
+        self.text = text.into();
+        self.until = now + NOTICE_SECS;
+    }
+
+    /// The notice to show at `now`, if it hasn't timed out yet
+    pub fn current(&self, now: f32) -> Option<&str> {
+        (now < self.until).then_some(self.text.as_str())
+    }
+}
+
+/// Marker to find the text entity so we can update it
+#[derive(Component)]
+pub struct StatusText;
+
+pub fn setup_status_text(mut commands: Commands) {
+    // create our UI root node
+    // this is the wrapper/container for the text
+    let root = commands
+        .spawn(NodeBundle {
+            // make it "always on top" by setting the Z index to maximum
+            // we want it to be displayed over all other UI
+            z_index: ZIndex::Global(i32::MAX),
+            style: Style {
+                position_type: PositionType::Absolute,
+                // position it at the top-left corner
+                // 1% away from the top window edge
+                left: Val::Percent(1.),
+                top: Val::Percent(1.),
+                // set bottom/right to Auto, so it can be
+                // automatically sized depending on the text
+                bottom: Val::Auto,
+                right: Val::Auto,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .id();
+    // create our text; it starts out empty (and so takes up no space) until there is
+    // something to report
+    let text_status = commands
+        .spawn((
+            StatusText,
+            TextBundle {
+                text: Text::from_section(
+                    "",
+                    TextStyle {
+                        font_size: 16.0,
+                        color: Color::WHITE,
+                        ..default()
+                    },
+                ),
+                // give it a dark background for readability
+                background_color: BackgroundColor(Color::BLACK.with_a(0.5)),
+                ..Default::default()
+            },
+        ))
+        .id();
+    commands.entity(root).push_children(&[text_status]);
+}
@@ -0,0 +1,77 @@
+use std::f32::consts::TAU;
+
+use bevy::prelude::*;
+
+use crate::{lerp_color, Configuration};
+
+/// Illuminance (in lux) of the sun at its highest, which lights the scene about as brightly as
+/// the point light does
+pub const SUN_ILLUMINANCE: f32 = 50_000.0;
+
+/// Fraction of a day already gone when the app starts, so it opens in the morning
+const START_TIME_OF_DAY: f32 = 0.05;
+
+/// Tilt (in radians) of the sun's path away from straight overhead, so it never casts shadows
+/// straight down
+const SUN_PATH_TILT: f32 = 0.4;
+
+/// Sun and sky color just as the sun rises or sets
+const DAWN_COLOR: Color = Color::rgb(1.0, 0.55, 0.3);
+
+/// Sky color with the sun well up
+const DAY_SKY_COLOR: Color = Color::rgb(0.45, 0.65, 0.95);
+
+/// Sky color at night
+const NIGHT_SKY_COLOR: Color = Color::rgb(0.01, 0.01, 0.04);
+
+/// Ambient light color at night (by day it's Configuration::ambient_color)
+const NIGHT_AMBIENT_COLOR: Color = Color::rgb(0.3, 0.35, 0.6);
+
+/// Ambient brightness at night, as a fraction of the daytime brightness
+/// (Configuration::ambient_brightness)
+const NIGHT_AMBIENT_FACTOR: f32 = 0.2;
+
+/// Marks the directional light that plays the sun
+#[derive(Component)]
+pub struct Sun;
+
+/// Move the sun across the sky over Configuration::day_length seconds (of simulation time, so
+/// the cycle stops while the app is paused), and match the sunlight, ambient light and sky color
+/// to how high it is.  A day length of 0 leaves the sun where it is.
+pub fn move_sun(
+    configuration: Res<Configuration>,
+    time: Res<Time>,
+    mut ambient_light: ResMut<AmbientLight>,
+    mut clear_color: ResMut<ClearColor>,
+    mut sun: Query<(&mut Transform, &mut DirectionalLight), With<Sun>>,
+) {
+    let Ok((mut transform, mut light)) = sun.get_single_mut() else {
+        return;
+    };
+    if configuration.day_length <= 0.0 {
+        return;
+    }
+
+    // 0 is sunrise, 0.25 noon, 0.5 sunset and the second half of the day is night.
+    let time_of_day =
+        (time.elapsed_seconds() / configuration.day_length + START_TIME_OF_DAY).fract();
+    let angle = time_of_day * TAU;
+    transform.rotation = Quat::from_rotation_z(SUN_PATH_TILT) * Quat::from_rotation_x(-angle);
+
+    // How high the sun is (-1 to 1), how far into the day it is (0 = night, through twilight,
+    // to 1 = day), and how far it is from the warm colors near the horizon.
+    let elevation = angle.sin();
+    let daylight = ((elevation + 0.1) / 0.3).clamp(0.0, 1.0);
+    let high = (elevation / 0.4).clamp(0.0, 1.0);
+
+    light.illuminance = SUN_ILLUMINANCE * daylight;
+    light.color = lerp_color(DAWN_COLOR, Color::WHITE, high);
+    ambient_light.color = lerp_color(NIGHT_AMBIENT_COLOR, configuration.ambient_color, daylight);
+    ambient_light.brightness = configuration.ambient_brightness
+        * (NIGHT_AMBIENT_FACTOR + (1.0 - NIGHT_AMBIENT_FACTOR) * daylight);
+    clear_color.0 = lerp_color(
+        NIGHT_SKY_COLOR,
+        lerp_color(DAWN_COLOR, DAY_SKY_COLOR, high),
+        daylight,
+    );
+}
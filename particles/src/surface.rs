@@ -0,0 +1,81 @@
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, VertexAttributeValues};
+use rand::Rng;
+
+/// A table for picking random points spread evenly over the surface of a mesh, built once from
+/// the mesh's positions and indices
+#[derive(Resource, Default)]
+pub struct SurfaceSampler {
+    /// Corners of every triangle, in the mesh's local space
+    triangles: Vec<[Vec3; 3]>,
+    /// Running total of the triangle areas, so a triangle can be picked in proportion to its area
+    cumulative_areas: Vec<f32>,
+}
+
+impl SurfaceSampler {
+    /// Builds the sampling table for `mesh`.  Meshes without (3D float) positions, or with no
+    /// triangles of any size, give an empty sampler.
+    pub fn from_mesh(mesh: &Mesh) -> Self {
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            warn!("Surface sampler: mesh has no usable positions");
+            return SurfaceSampler::default();
+        };
+
+        // unindexed meshes simply list the corners of each triangle in turn
+        let indices: Vec<usize> = match mesh.indices() {
+            Some(Indices::U16(indices)) => indices.iter().map(|&i| i as usize).collect(),
+            Some(Indices::U32(indices)) => indices.iter().map(|&i| i as usize).collect(),
+            None => (0..positions.len()).collect(),
+        };
+
+        let mut sampler = SurfaceSampler::default();
+        let mut total_area = 0.0;
+        for corners in indices.chunks_exact(3) {
+            let triangle = [
+                Vec3::from(positions[corners[0]]),
+                Vec3::from(positions[corners[1]]),
+                Vec3::from(positions[corners[2]]),
+            ];
+            let area = (triangle[1] - triangle[0])
+                .cross(triangle[2] - triangle[0])
+                .length()
+                * 0.5;
+            // degenerate triangles could never be picked and have no normal, so leave them out
+            if area <= f32::EPSILON {
+                continue;
+            }
+            total_area += area;
+            sampler.triangles.push(triangle);
+            sampler.cumulative_areas.push(total_area);
+        }
+        info!(
+            "Surface sampler: {} triangles, total area {:.2}",
+            sampler.triangles.len(),
+            total_area
+        );
+        sampler
+    }
+
+    /// Picks a random point on the surface and returns it along with the (unit) normal of the
+    /// triangle it lies on, or None if there's no surface to pick from
+    pub fn sample(&self, rng: &mut impl Rng) -> Option<(Vec3, Vec3)> {
+        let total_area = *self.cumulative_areas.last()?;
+
+        // pick a triangle with a probability proportional to its area
+        let target = rng.gen::<f32>() * total_area;
+        let index = self
+            .cumulative_areas
+            .partition_point(|&area| area <= target)
+            .min(self.triangles.len() - 1);
+        let [a, b, c] = self.triangles[index];
+
+        // then a point inside it; the square root keeps the points from bunching up at corner a
+        let r1 = rng.gen::<f32>().sqrt();
+        let r2 = rng.gen::<f32>();
+        let point = a * (1.0 - r1) + b * (r1 * (1.0 - r2)) + c * (r1 * r2);
+        let normal = (b - a).cross(c - a).normalize();
+        Some((point, normal))
+    }
+}
@@ -0,0 +1,81 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+
+use crate::{Configuration, ParticleMarker};
+
+/// Longest trail (in points) allowed, whatever the configuration asks for, to bound the memory
+/// each particle holds on to
+pub const MAX_TRAIL_LENGTH: usize = 120;
+
+/// Color of the head of a trail; it fades to transparent towards the tail
+const TRAIL_COLOR: Color = Color::rgb(1.0, 0.9, 0.6);
+
+/// The most recent positions of a particle, oldest first
+#[derive(Component, Default)]
+pub struct TrailPoints {
+    points: VecDeque<Vec3>,
+}
+
+/// Record where every particle is this frame, dropping the oldest points once a trail is long
+/// enough.  Trails are only kept while they're switched on, since this touches every particle
+/// every frame (and drawing them adds a line segment per point).
+pub fn record_trails(
+    configuration: Res<Configuration>,
+    mut query: Query<(&Transform, &mut TrailPoints), With<ParticleMarker>>,
+) {
+    if !configuration.show_trails {
+        // free up the old trails once, when they're switched off
+        if configuration.is_changed() {
+            for (_, mut trail) in &mut query {
+                *trail = TrailPoints::default();
+            }
+        }
+        return;
+    }
+
+    let length = configuration.trail_length.min(MAX_TRAIL_LENGTH);
+    for (transform, mut trail) in &mut query {
+        trail.points.push_back(transform.translation);
+        while trail.points.len() > length {
+            trail.points.pop_front();
+        }
+    }
+}
+
+/// Draw every particle's trail as a line that fades out towards its oldest point
+pub fn draw_trails(
+    configuration: Res<Configuration>,
+    query: Query<&TrailPoints, With<ParticleMarker>>,
+    mut gizmos: Gizmos,
+) {
+    if !configuration.show_trails {
+        return;
+    }
+
+    for trail in &query {
+        let count = trail.points.len();
+        if count < 2 {
+            continue;
+        }
+        gizmos.linestrip_gradient(trail.points.iter().enumerate().map(|(i, &point)| {
+            let alpha = (i + 1) as f32 / count as f32;
+            (point, TRAIL_COLOR.with_a(alpha))
+        }));
+    }
+}
+
+/// Switch the particle trails on or off when pressing U
+pub fn toggle_trails(mut configuration: ResMut<Configuration>, kbd: Res<Input<KeyCode>>) {
+    if kbd.just_pressed(KeyCode::U) {
+        configuration.show_trails = !configuration.show_trails;
+        info!(
+            "Particle trails: {}",
+            if configuration.show_trails {
+                "on"
+            } else {
+                "off"
+            }
+        );
+    }
+}